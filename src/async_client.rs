@@ -0,0 +1,475 @@
+use crate::bencode::{BencodeDecoder, Message};
+use crate::client::{EvalEvent, EvalResult, NreplError, ServerDescription};
+use crate::message::{Request, RequestMessage, Response};
+use crate::routing::{Route, Routed, Router};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, timeout, timeout_at};
+
+/// An asynchronous client for interacting with an nREPL server over TCP.
+///
+/// `AsyncNreplClient` mirrors [`NreplClient`](crate::client::NreplClient) for
+/// tokio-based applications. A background task reads responses and routes them
+/// to the waiting request by `id`, and all waits are bounded with tokio timers.
+///
+/// Call [`close`](Self::close) when done with the client. Dropping it cannot
+/// wait for the server, so it only queues a `close` for the session if the
+/// socket has room, and the session may be left open on the server.
+///
+/// A request whose write times out, or whose future is dropped mid-write, may
+/// leave half a message on the wire, so the connection is closed and later
+/// requests fail with `NreplError::ConnectionClosed`.
+pub struct AsyncNreplClient {
+    writer: OwnedWriteHalf,
+    session: Option<String>,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    eval_timeout: Duration,
+    /// Set while a request is being written, so a write that was cancelled
+    /// partway is noticed by the next one.
+    writing: bool,
+    routes: Arc<Routes>,
+    reader: JoinHandle<()>,
+}
+
+/// Routes responses to the requests waiting on them, by message `id`.
+type Routes = Router<UnboundedSender<Routed>>;
+
+/// Responses to a single request, unregistered from the routing table on drop.
+struct Pending {
+    route: Route<UnboundedSender<Routed>>,
+    receiver: UnboundedReceiver<Routed>,
+}
+
+impl Pending {
+    fn is_done(&self) -> bool {
+        self.route.is_done()
+    }
+
+    async fn next_response(&mut self, deadline: Instant) -> Result<Message, NreplError> {
+        match timeout_at(deadline, self.receiver.recv()).await {
+            Ok(Some(response)) => self.route.observe(response),
+            Ok(None) => Err(NreplError::ConnectionClosed),
+            Err(_) => Err(NreplError::Timeout),
+        }
+    }
+}
+
+async fn read_loop(mut reader: OwnedReadHalf, routes: Arc<Routes>) {
+    let mut decoder = BencodeDecoder::new();
+    let mut temp_buffer = [0u8; 4096];
+
+    let error = loop {
+        match decoder.next_message() {
+            Ok(Some(message)) => {
+                routes.dispatch(message);
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => break e,
+        }
+
+        match reader.read(&mut temp_buffer).await {
+            Ok(0) => break NreplError::ConnectionClosed,
+            Ok(n) => decoder.feed(&temp_buffer[..n]),
+            Err(e) => match e.kind() {
+                ErrorKind::Interrupted => {}
+                ErrorKind::UnexpectedEof
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset => break NreplError::ConnectionClosed,
+                _ => break NreplError::IoError(e),
+            },
+        }
+    };
+
+    routes.fail_all(&error);
+}
+
+impl AsyncNreplClient {
+    /// Connects to an nREPL server at the given host and port.
+    ///
+    /// Must be called from within a tokio runtime, which runs the reader task.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The hostname or IP address of the nREPL server.
+    /// * `port` - The port number of the nREPL server.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a new `AsyncNreplClient` if successful,
+    /// or an `NreplError` if the connection fails.
+    pub async fn connect(host: &str, port: u16) -> Result<Self, NreplError> {
+        let stream = TcpStream::connect(format!("{}:{}", host, port)).await?;
+        let (reader, writer) = stream.into_split();
+
+        let routes = Arc::new(Routes::new());
+        let reader = tokio::spawn(read_loop(reader, Arc::clone(&routes)));
+
+        Ok(AsyncNreplClient {
+            writer,
+            session: None,
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            eval_timeout: Duration::from_secs(60),
+            writing: false,
+            routes,
+            reader,
+        })
+    }
+
+    /// Sets the read and write timeouts for the client connection.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_timeout` - Maximum wait for the reply to a single-response
+    ///   operation.
+    /// * `write_timeout` - Maximum wait for a request to be written.
+    pub fn set_timeouts(&mut self, read_timeout: Duration, write_timeout: Duration) {
+        self.read_timeout = read_timeout;
+        self.write_timeout = write_timeout;
+    }
+
+    /// Sets the timeout for `eval`, which takes no timeout of its own.
+    /// Defaults to 60 seconds.
+    pub fn set_eval_timeout(&mut self, timeout: Duration) {
+        self.eval_timeout = timeout;
+    }
+
+    /// Creates a new session on the nREPL server and updates the client session.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the new session ID as a `String` if successful,
+    /// or an `NreplError` if the operation fails.
+    pub async fn clone_session(&mut self) -> Result<String, NreplError> {
+        let mut pending = self.send_request(Request::Clone.into()).await?;
+        let response = pending
+            .next_response(Instant::now() + self.read_timeout)
+            .await?;
+
+        if let Some(session_id) = Response::from_message(response).new_session {
+            self.session = Some(session_id.clone());
+            return Ok(session_id);
+        }
+
+        Err(NreplError::Other(
+            "Failed to get session from clone response".to_string(),
+        ))
+    }
+
+    /// Evaluates the given Clojure code on the nREPL server, waiting at most
+    /// the timeout set with `set_eval_timeout`.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails.
+    pub async fn eval(&mut self, code: &str) -> Result<EvalResult, NreplError> {
+        self.eval_with_timeout(code, self.eval_timeout).await
+    }
+
+    /// Evaluates the given Clojure code on the nREPL server with a custom timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for evaluation.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails or times out.
+    pub async fn eval_with_timeout(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+    ) -> Result<EvalResult, NreplError> {
+        let deadline = Instant::now() + timeout;
+
+        // Ensure there a session already otherwise create new
+        if self.session.is_none() {
+            self.clone_session().await?;
+        }
+
+        let mut request = RequestMessage::new(Request::eval(code));
+        request.session = self.session.clone();
+
+        let mut pending = self.send_request(request).await?;
+        let mut result = EvalResult {
+            session: self.session.clone(),
+            ..EvalResult::default()
+        };
+        while !pending.is_done() {
+            let response = pending.next_response(deadline).await?;
+            for event in EvalEvent::from_response(&response) {
+                result.apply(&event);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Requests a description of the nREPL server's capabilities and operations.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ServerDescription` if successful,
+    /// or an `NreplError` if the operation fails.
+    pub async fn describe(&mut self) -> Result<ServerDescription, NreplError> {
+        let mut pending = self.send_request(Request::Describe.into()).await?;
+        let response = pending
+            .next_response(Instant::now() + self.read_timeout)
+            .await?;
+        Ok(ServerDescription::from_response(&response))
+    }
+
+    /// Sends an interrupt request to the nREPL server for the current session.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the interrupt was sent successfully,
+    /// or an `NreplError` if the operation fails.
+    pub async fn interrupt(&mut self) -> Result<(), NreplError> {
+        if let Some(session) = self.session.clone() {
+            let request = Request::Interrupt { interrupt_id: None };
+            let mut pending = self
+                .send_request(RequestMessage::new(request).in_session(&session))
+                .await?;
+            let _response = pending
+                .next_response(Instant::now() + self.read_timeout)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Closes the session on the nREPL server and shuts down the connection.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the connection is closed. Failures while ending
+    /// the session are ignored.
+    pub async fn close(&mut self) -> Result<(), NreplError> {
+        if let Some(session) = self.session.take() {
+            let request = RequestMessage::new(Request::Close).in_session(&session);
+
+            // Best effort - don't fail if close fails
+            if let Ok(mut pending) = self.send_request(request).await {
+                let _ = pending
+                    .next_response(Instant::now() + self.read_timeout)
+                    .await;
+            }
+        }
+        let _ = self.writer.shutdown().await;
+        Ok(())
+    }
+
+    async fn send_request(&mut self, mut request: RequestMessage) -> Result<Pending, NreplError> {
+        if self.writing {
+            // The caller dropped the future of an earlier write partway
+            self.break_connection().await;
+            return Err(NreplError::ConnectionClosed);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        request.id = Some(id.clone());
+
+        let (route, receiver) = Route::register(&self.routes, id)?;
+        let pending = Pending { route, receiver };
+
+        let encoded =
+            serde_bencode::to_bytes(&request).map_err(|e| NreplError::ParseError(e.to_string()))?;
+        self.writing = true;
+        let written = timeout(self.write_timeout, self.writer.write_all(&encoded)).await;
+        self.writing = false;
+        let error = match written {
+            Ok(Ok(())) => return Ok(pending),
+            Ok(Err(e)) => match e.kind() {
+                ErrorKind::BrokenPipe
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset => NreplError::ConnectionClosed,
+                _ => NreplError::IoError(e),
+            },
+            Err(_) => NreplError::Timeout,
+        };
+        self.break_connection().await;
+        Err(error)
+    }
+
+    /// Gives up on the connection once a request may have been written only
+    /// in part, since the server can't find where the next one starts.
+    /// Requests in flight, and any sent later, fail with `ConnectionClosed`.
+    async fn break_connection(&mut self) {
+        self.routes.fail_all(&NreplError::ConnectionClosed);
+        self.reader.abort();
+        let _ = self.writer.shutdown().await;
+    }
+}
+
+impl Drop for AsyncNreplClient {
+    fn drop(&mut self) {
+        // Best effort - a write that would block is skipped, and so is one
+        // that would follow half a request
+        if let Some(session) = self.session.take().filter(|_| !self.writing) {
+            let mut request = RequestMessage::new(Request::Close).in_session(&session);
+            request.id = Some(uuid::Uuid::new_v4().to_string());
+            if let Ok(encoded) = serde_bencode::to_bytes(&request) {
+                let _ = self.writer.try_write(&encoded);
+            }
+        }
+        self.reader.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+
+    #[tokio::test]
+    async fn test_robust_connection() {
+        let server = MockServer::start_standard().unwrap();
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+
+        assert!(client.describe().await.is_ok());
+        assert!(client.clone_session().await.is_ok());
+
+        let result = client.eval("(+ 1 1)").await.unwrap();
+        assert_eq!(result.value(), Some("2"));
+
+        let result = client
+            .eval_with_timeout("(Thread/sleep 100)", Duration::from_millis(50))
+            .await;
+        assert!(matches!(result, Err(NreplError::Timeout)));
+
+        let result = client
+            .eval("(require '[clojure.string :as str])")
+            .await
+            .unwrap();
+        assert_eq!(result.value(), Some("nil"));
+        let result = client.eval("(str/reverse \"abc\")").await.unwrap();
+        assert_eq!(result.value(), Some("\"cba\""));
+
+        assert!(client.interrupt().await.is_ok());
+        assert!(client.close().await.is_ok());
+        assert!(server.sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_eval_creates_session_on_demand() {
+        let server = MockServer::start_standard().unwrap();
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+
+        let result = client.eval("(+ 1 1)").await.unwrap();
+        assert_eq!(result.value(), Some("2"));
+        assert_eq!(server.sessions(), vec![client.session.clone().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn test_eval_uses_eval_timeout() {
+        let server = MockServer::start_standard().unwrap();
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+        client.set_timeouts(Duration::from_millis(50), Duration::from_secs(1));
+        assert!(client.eval("(Thread/sleep 100)").await.is_ok());
+
+        client.set_eval_timeout(Duration::from_millis(50));
+        let result = client.eval("(Thread/sleep 100)").await;
+        assert!(matches!(result, Err(NreplError::Timeout)));
+    }
+
+    /// Connects to a server that never reads, so writes stall once the
+    /// socket buffers fill. The returned handle keeps the server's end open.
+    async fn connect_to_stalled_server() -> (AsyncNreplClient, JoinHandle<TcpStream>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = tokio::spawn(async move { listener.accept().await.unwrap().0 });
+        let mut client = AsyncNreplClient::connect("127.0.0.1", port).await.unwrap();
+        client.session = Some("stalled".to_string());
+        (client, accepted)
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout_breaks_the_connection() {
+        let (mut client, _server) = connect_to_stalled_server().await;
+        client.set_timeouts(Duration::from_secs(1), Duration::from_millis(200));
+
+        let code = "x".repeat(64 * 1024 * 1024);
+        let result = client.eval(&code).await;
+        assert!(matches!(result, Err(NreplError::Timeout)));
+        let result = client.describe().await;
+        assert!(matches!(result, Err(NreplError::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_write_breaks_the_connection() {
+        let (mut client, _server) = connect_to_stalled_server().await;
+
+        let code = "x".repeat(64 * 1024 * 1024);
+        tokio::select! {
+            _ = client.eval(&code) => panic!("the write should stall"),
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+        }
+        let result = client.describe().await;
+        assert!(matches!(result, Err(NreplError::ConnectionClosed)));
+        let result = client.describe().await;
+        assert!(matches!(result, Err(NreplError::ConnectionClosed)));
+    }
+
+    #[tokio::test]
+    async fn test_drop_closes_session() {
+        let server = MockServer::start_standard().unwrap();
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+        client.eval("(+ 1 1)").await.unwrap();
+        assert_eq!(server.sessions().len(), 1);
+
+        drop(client);
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !server.sessions().is_empty() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(server.sessions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_split_packets_are_reassembled() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(+ 1 2 3)", Reply::new().out("six\n").value("6").done());
+        server.set_chunk_size(Some(3));
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+
+        let result = client.eval("(+ 1 2 3)").await.unwrap();
+        assert_eq!(result.output, "six\n");
+        assert_eq!(result.value(), Some("6"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_drop_is_reported() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(+ 1 1)", Reply::new().disconnect());
+        let mut client = AsyncNreplClient::connect("127.0.0.1", server.port())
+            .await
+            .unwrap();
+
+        let result = client.eval("(+ 1 1)").await;
+        assert!(matches!(result, Err(NreplError::ConnectionClosed)));
+    }
+}
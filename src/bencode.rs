@@ -0,0 +1,386 @@
+use crate::client::NreplError;
+use serde_bencode::value::Value;
+use std::collections::HashMap;
+
+/// A single nREPL message: a bencode dictionary keyed by field name.
+pub type Message = HashMap<String, Value>;
+
+/// Default upper bound on the size of a single encoded message (1 MB).
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// How deeply values may nest in a message, so hostile input can't recurse a
+/// decoder off the end of a 2 MB thread stack, even in debug builds.
+pub(crate) const MAX_DEPTH: usize = 256;
+
+/// Where the scanner is inside the value it is currently walking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    /// Expecting the first byte of a value (or the `e` closing a container).
+    Token,
+    /// Inside an `i...e` integer.
+    Int,
+    /// Reading the decimal length prefix of a byte string.
+    StrLen(usize),
+    /// Skipping the given number of remaining byte-string bytes.
+    StrBody(usize),
+}
+
+/// An incremental bencode framing decoder.
+///
+/// `BencodeDecoder` accepts bytes in arbitrarily sized chunks via [`feed`](Self::feed)
+/// and yields each complete top-level message exactly once from
+/// [`next_message`](Self::next_message). Bytes following a complete message are kept
+/// for the next call, so several messages arriving in one read are all delivered.
+///
+/// The scanner remembers how far it got, so every byte is examined once while
+/// framing and once more when the finished message is decoded.
+pub struct BencodeDecoder {
+    buffer: Vec<u8>,
+    /// Offset of the first byte of the message currently being framed.
+    start: usize,
+    /// Offset of the next byte the scanner has not looked at yet.
+    pos: usize,
+    /// Container nesting depth of the message being framed.
+    depth: usize,
+    state: ScanState,
+    max_message_size: usize,
+}
+
+impl Default for BencodeDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BencodeDecoder {
+    /// Creates an empty decoder with the default message size limit.
+    ///
+    /// # Returns
+    ///
+    /// Returns a new `BencodeDecoder` with no buffered data.
+    pub fn new() -> Self {
+        BencodeDecoder {
+            buffer: Vec::new(),
+            start: 0,
+            pos: 0,
+            depth: 0,
+            state: ScanState::Token,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets the largest encoded message, in bytes, the decoder will buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_message_size` - Maximum size of a single encoded message.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Appends newly received bytes to the decoder's buffer.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The bytes read from the connection.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Reclaim space taken by messages that were already handed out.
+        if self.start > 0 && self.start >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.start);
+            self.pos -= self.start;
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the number of buffered bytes not yet returned as a message.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() - self.start
+    }
+
+    /// Decodes the next complete message from the buffered bytes, if there is one.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(Some(message))` when a full message is available, `Ok(None)` when
+    /// more bytes are needed, an `NreplError::ParseError` if the input is not
+    /// valid bencode, or `NreplError::LimitExceeded` if a message is larger than
+    /// the size limit.
+    pub fn next_message(&mut self) -> Result<Option<Message>, NreplError> {
+        let end = match self.scan()? {
+            Some(end) => end,
+            None => {
+                if self.buffered_len() > self.max_message_size {
+                    return Err(self.too_large());
+                }
+                return Ok(None);
+            }
+        };
+
+        let frame = &self.buffer[self.start..end];
+        if frame.len() > self.max_message_size {
+            return Err(self.too_large());
+        }
+        let decoded = serde_bencode::from_bytes::<Message>(frame)
+            .map_err(|e| NreplError::ParseError(e.to_string()));
+
+        // Move past the frame even if it failed to decode, so a bad message
+        // is reported once instead of on every subsequent call.
+        self.start = end;
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+            self.pos = 0;
+        }
+
+        decoded.map(Some)
+    }
+
+    fn too_large(&self) -> NreplError {
+        NreplError::LimitExceeded {
+            setting: "max_message_size",
+            limit: format!("{} bytes", self.max_message_size),
+        }
+    }
+
+    /// Advances the scanner over the buffered bytes.
+    ///
+    /// Returns the end offset of the first complete top-level value, or `None`
+    /// if the buffer ends before the value does.
+    fn scan(&mut self) -> Result<Option<usize>, NreplError> {
+        while self.pos < self.buffer.len() {
+            let byte = self.buffer[self.pos];
+            let value_finished = match self.state {
+                ScanState::Token => {
+                    self.pos += 1;
+                    match byte {
+                        b'd' | b'l' => {
+                            self.depth += 1;
+                            if self.depth > MAX_DEPTH {
+                                return Err(NreplError::ParseError(format!(
+                                    "nesting too deep at offset {}",
+                                    self.pos - 1
+                                )));
+                            }
+                            false
+                        }
+                        b'e' => {
+                            if self.depth == 0 {
+                                return Err(unexpected(byte, self.pos - 1));
+                            }
+                            self.depth -= 1;
+                            true
+                        }
+                        b'i' => {
+                            self.state = ScanState::Int;
+                            false
+                        }
+                        b'0'..=b'9' => {
+                            self.state = ScanState::StrLen((byte - b'0') as usize);
+                            false
+                        }
+                        _ => return Err(unexpected(byte, self.pos - 1)),
+                    }
+                }
+                ScanState::Int => {
+                    self.pos += 1;
+                    match byte {
+                        b'e' => {
+                            self.state = ScanState::Token;
+                            true
+                        }
+                        b'-' | b'0'..=b'9' => false,
+                        _ => return Err(unexpected(byte, self.pos - 1)),
+                    }
+                }
+                ScanState::StrLen(len) => {
+                    self.pos += 1;
+                    match byte {
+                        b':' if len == 0 => {
+                            self.state = ScanState::Token;
+                            true
+                        }
+                        b':' => {
+                            self.state = ScanState::StrBody(len);
+                            false
+                        }
+                        b'0'..=b'9' => {
+                            let len = len
+                                .checked_mul(10)
+                                .and_then(|l| l.checked_add((byte - b'0') as usize))
+                                .ok_or_else(|| {
+                                    NreplError::ParseError(
+                                        "Byte string length overflow".to_string(),
+                                    )
+                                })?;
+                            self.state = ScanState::StrLen(len);
+                            false
+                        }
+                        _ => return Err(unexpected(byte, self.pos - 1)),
+                    }
+                }
+                ScanState::StrBody(remaining) => {
+                    let available = self.buffer.len() - self.pos;
+                    let taken = remaining.min(available);
+                    self.pos += taken;
+                    if taken == remaining {
+                        self.state = ScanState::Token;
+                        true
+                    } else {
+                        self.state = ScanState::StrBody(remaining - taken);
+                        false
+                    }
+                }
+            };
+
+            if value_finished && self.depth == 0 {
+                return Ok(Some(self.pos));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn unexpected(byte: u8, offset: usize) -> NreplError {
+    NreplError::ParseError(format!(
+        "Unexpected byte {:?} at offset {}",
+        byte as char, offset
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn decode_all(decoder: &mut BencodeDecoder) -> Vec<Message> {
+        let mut messages = Vec::new();
+        while let Some(msg) = decoder.next_message().expect("valid bencode") {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_partial_message_waits_for_more_bytes() {
+        let mut decoder = BencodeDecoder::new();
+        decoder.feed(b"d2:id1:15:value");
+        assert!(decoder.next_message().unwrap().is_none());
+        decoder.feed(b"1:2e");
+        let msg = decoder.next_message().unwrap().unwrap();
+        assert_eq!(msg.get("value"), Some(&Value::Bytes(b"2".to_vec())));
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_multiple_messages_in_one_chunk() {
+        let mut decoder = BencodeDecoder::new();
+        decoder.feed(b"d2:id1:1ed2:id1:2ed2:id");
+        let messages = decode_all(&mut decoder);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].get("id"), Some(&Value::Bytes(b"2".to_vec())));
+        assert_eq!(decoder.buffered_len(), 5);
+    }
+
+    #[test]
+    fn test_invalid_input_is_a_parse_error() {
+        let mut decoder = BencodeDecoder::new();
+        decoder.feed(b"x");
+        assert!(matches!(
+            decoder.next_message(),
+            Err(NreplError::ParseError(_))
+        ));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let nested = |depth: usize| {
+            let mut bytes = b"d1:x".to_vec();
+            bytes.extend(std::iter::repeat_n(b'l', depth - 1));
+            bytes.extend(std::iter::repeat_n(b'e', depth));
+            bytes
+        };
+        let mut decoder = BencodeDecoder::new();
+        decoder.feed(&nested(MAX_DEPTH));
+        assert!(decoder.next_message().unwrap().is_some());
+
+        let mut decoder = BencodeDecoder::new();
+        decoder.feed(&nested(100_000));
+        let err = decoder.next_message().unwrap_err();
+        assert!(err.to_string().contains("nesting too deep"), "{}", err);
+    }
+
+    #[test]
+    fn test_message_size_limit() {
+        let mut decoder = BencodeDecoder::new();
+        decoder.set_max_message_size(16);
+        decoder.feed(b"d5:value100:");
+        decoder.feed(&[b'x'; 20]);
+        assert!(matches!(
+            decoder.next_message(),
+            Err(NreplError::LimitExceeded {
+                setting: "max_message_size",
+                ..
+            })
+        ));
+    }
+
+    fn arb_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            prop::collection::vec(any::<u8>(), 0..32).prop_map(Value::Bytes),
+            any::<i64>().prop_map(Value::Int),
+        ];
+        leaf.prop_recursive(3, 32, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(Value::List),
+                prop::collection::hash_map(prop::collection::vec(any::<u8>(), 0..8), inner, 0..6)
+                    .prop_map(Value::Dict),
+            ]
+        })
+    }
+
+    fn arb_message() -> impl Strategy<Value = Message> {
+        prop::collection::hash_map("[a-z-]{1,12}", arb_value(), 0..6)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_chunked_stream_matches_serde_bencode(
+            messages in prop::collection::vec(arb_message(), 1..5),
+            chunk_sizes in prop::collection::vec(1usize..64, 1..32),
+        ) {
+            let encoded: Vec<Vec<u8>> = messages
+                .iter()
+                .map(|m| serde_bencode::to_bytes(m).unwrap())
+                .collect();
+            let stream = encoded.concat();
+
+            let mut decoder = BencodeDecoder::new();
+            let mut decoded = Vec::new();
+            let mut offset = 0;
+            let mut sizes = chunk_sizes.iter().cycle();
+            while offset < stream.len() {
+                let end = (offset + sizes.next().unwrap()).min(stream.len());
+                decoder.feed(&stream[offset..end]);
+                decoded.extend(decode_all(&mut decoder));
+                offset = end;
+            }
+
+            let expected: Vec<Message> = encoded
+                .iter()
+                .map(|bytes| serde_bencode::from_bytes::<Message>(bytes).unwrap())
+                .collect();
+            prop_assert_eq!(decoded, expected);
+            prop_assert_eq!(decoder.buffered_len(), 0);
+        }
+
+        #[test]
+        fn prop_truncated_message_is_never_yielded(message in arb_message(), cut in any::<prop::sample::Index>()) {
+            let bytes = serde_bencode::to_bytes(&message).unwrap();
+            let cut = cut.index(bytes.len());
+            let mut decoder = BencodeDecoder::new();
+            decoder.feed(&bytes[..cut]);
+            prop_assert!(decoder.next_message().unwrap().is_none());
+        }
+    }
+}
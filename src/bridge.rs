@@ -0,0 +1,673 @@
+use crate::bencode::Message;
+use crate::builder::NreplClientBuilder;
+use crate::client::{NreplClient, PendingRequest};
+use crate::message::{Request, RequestMessage};
+use serde_bencode::value::Value;
+use serde_json::{Map, Value as Json};
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+use tungstenite::handshake::server::{
+    Callback, ErrorResponse, Request as Upgrade, Response as Accepted,
+};
+use tungstenite::http::StatusCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::{Message as WsMessage, WebSocket};
+
+/// How long the bridge waits for a WebSocket frame before checking for replies.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a browser gets to complete the WebSocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Relays nREPL messages between WebSocket clients and an nREPL server.
+///
+/// Browsers can't open raw TCP sockets, so the bridge accepts WebSocket
+/// connections and exchanges nREPL messages as JSON text frames, one message
+/// per frame. Each WebSocket gets its own backend connection and session; the
+/// `session` key of incoming messages is ignored and replaced with it.
+///
+/// Replies carry the `id` the browser sent. A WebSocket with no requests in
+/// flight that stays silent for the idle timeout is closed, along with its
+/// session.
+///
+/// Any page a browser visits can open a WebSocket, so upgrades must carry an
+/// `Origin` header the bridge allows, and a `token` query parameter when a
+/// token is set. Other upgrades are refused with `403 Forbidden`.
+///
+/// ```no_run
+/// use nrepl_client_server_demo::bridge::WsBridge;
+/// use std::time::Duration;
+///
+/// let bridge = WsBridge::bind("127.0.0.1:7889", "127.0.0.1", 7888)?
+///     .allowed_origins(["http://localhost:3000"])
+///     .token("s3cret")
+///     .idle_timeout(Duration::from_secs(600));
+/// bridge.run()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct WsBridge {
+    listener: TcpListener,
+    backend_host: String,
+    backend_port: u16,
+    client_builder: NreplClientBuilder,
+    idle_timeout: Duration,
+    access: Access,
+}
+
+impl WsBridge {
+    /// Listens for WebSocket connections and relays them to an nREPL server.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to accept WebSocket connections on.
+    /// * `backend_host` - The hostname or IP address of the nREPL server.
+    /// * `backend_port` - The port number of the nREPL server.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the bridge, or an `io::Error` if `addr`
+    /// cannot be bound. The backend is only contacted once a browser connects.
+    pub fn bind(addr: &str, backend_host: &str, backend_port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let access = Access {
+            host: listener.local_addr()?.ip(),
+            origins: None,
+            token: None,
+        };
+        Ok(WsBridge {
+            listener,
+            backend_host: backend_host.to_string(),
+            backend_port,
+            client_builder: NreplClientBuilder::new(),
+            idle_timeout: Duration::from_secs(300),
+            access,
+        })
+    }
+
+    /// Sets the origins allowed to open WebSockets, such as
+    /// `http://localhost:3000`.
+    ///
+    /// By default only pages served from this host are allowed: origins whose
+    /// host is `localhost`, a loopback address or the address the bridge is
+    /// bound to, on any scheme and port.
+    pub fn allowed_origins<I, S>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.access.origins = Some(
+            origins
+                .into_iter()
+                .map(|origin| origin.into().trim_end_matches('/').to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    /// Requires WebSocket URLs to carry `?token=<token>`, percent-encoded as
+    /// `encodeURIComponent` does. Off by default.
+    pub fn token(mut self, token: &str) -> Self {
+        self.access.token = Some(token.to_string());
+        self
+    }
+
+    /// Sets how long an idle WebSocket is kept open. Defaults to five minutes.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the settings used for backend connections, for example TLS.
+    pub fn client_builder(mut self, builder: NreplClientBuilder) -> Self {
+        self.client_builder = builder;
+        self
+    }
+
+    /// Returns the address the bridge accepts WebSocket connections on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts WebSocket connections until the listener fails, serving each
+    /// on its own thread.
+    pub fn run(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) if e.kind() == ErrorKind::ConnectionAborted => continue,
+                Err(e) => return Err(e),
+            };
+            let backend = Backend {
+                host: self.backend_host.clone(),
+                port: self.backend_port,
+                builder: self.client_builder.clone(),
+            };
+            let idle_timeout = self.idle_timeout;
+            let access = self.access.clone();
+            thread::Builder::new()
+                .name("nrepl-ws-bridge".to_string())
+                .spawn(move || {
+                    let _ = serve_websocket(stream, &backend, access, idle_timeout);
+                })?;
+        }
+        Ok(())
+    }
+}
+
+struct Backend {
+    host: String,
+    port: u16,
+    builder: NreplClientBuilder,
+}
+
+/// Who may open a WebSocket on the bridge.
+#[derive(Clone)]
+struct Access {
+    /// The address the bridge is bound to.
+    host: IpAddr,
+    /// The allowed origins, or `None` for same-host origins only.
+    origins: Option<Vec<String>>,
+    token: Option<String>,
+}
+
+impl Callback for Access {
+    /// Accepts a WebSocket upgrade, or refuses it with `403 Forbidden`.
+    fn on_request(self, request: &Upgrade, response: Accepted) -> Result<Accepted, ErrorResponse> {
+        match self.refusal(request) {
+            Some(reason) => {
+                let mut refused = ErrorResponse::new(Some(reason.to_string()));
+                *refused.status_mut() = StatusCode::FORBIDDEN;
+                Err(refused)
+            }
+            None => Ok(response),
+        }
+    }
+}
+
+impl Access {
+    /// Returns why an upgrade request is refused, or `None` to accept it.
+    fn refusal(&self, request: &Upgrade) -> Option<&'static str> {
+        let origin = request
+            .headers()
+            .get("origin")
+            .and_then(|origin| origin.to_str().ok());
+        if !origin.is_some_and(|origin| self.allows_origin(origin)) {
+            return Some("origin not allowed");
+        }
+        if let Some(token) = &self.token {
+            let query = request.uri().query().unwrap_or_default();
+            let mut sent = query
+                .split('&')
+                .filter_map(|pair| pair.strip_prefix("token="))
+                .filter_map(percent_decode);
+            if !sent.any(|sent| constant_time_eq(&sent, token.as_bytes())) {
+                return Some("missing or wrong token");
+            }
+        }
+        None
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        if let Some(origins) = &self.origins {
+            return origins.contains(&origin);
+        }
+        let Some((_, authority)) = origin.split_once("://") else {
+            return false;
+        };
+        let host = match authority.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or(rest),
+            None => authority.split(':').next().unwrap_or(authority),
+        };
+        host == "localhost"
+            || host
+                .parse::<IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback() || ip == self.host)
+    }
+}
+
+/// Decodes `%XX` escapes in a query parameter, or returns `None` if one is
+/// malformed.
+fn percent_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(bytes)
+}
+
+/// Compares without stopping at the first difference, so response times
+/// don't reveal how much of a guessed token was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// A relayed request: the id the browser chose and the backend's handle.
+struct InFlight {
+    browser_id: Option<Json>,
+    pending: PendingRequest,
+}
+
+fn serve_websocket(
+    stream: TcpStream,
+    backend: &Backend,
+    access: Access,
+    idle_timeout: Duration,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut ws = tungstenite::accept_hdr(stream.try_clone()?, access).map_err(io::Error::other)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+
+    let mut client = match backend.builder.connect(&backend.host, backend.port) {
+        Ok(client) => client,
+        Err(e) => {
+            close_websocket(
+                &mut ws,
+                CloseCode::Error,
+                &format!("nREPL unavailable: {}", e),
+            );
+            return Ok(());
+        }
+    };
+    if let Err(e) = client.clone_session() {
+        close_websocket(
+            &mut ws,
+            CloseCode::Error,
+            &format!("nREPL session failed: {}", e),
+        );
+        return Ok(());
+    }
+
+    let mut in_flight: Vec<InFlight> = Vec::new();
+    let mut last_activity = Instant::now();
+    loop {
+        match ws.read() {
+            Ok(WsMessage::Text(text)) => {
+                last_activity = Instant::now();
+                match relay_request(&mut client, &in_flight, text.as_str()) {
+                    Ok(request) => in_flight.push(request),
+                    Err((browser_id, error)) => {
+                        send_json(&mut ws, error_reply(browser_id, &error))?;
+                    }
+                }
+            }
+            Ok(WsMessage::Close(_)) => break,
+            Ok(_) => last_activity = Instant::now(),
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+
+        if !forward_replies(&mut ws, &mut in_flight)? {
+            close_websocket(&mut ws, CloseCode::Error, "nREPL connection lost");
+            break;
+        }
+        if !in_flight.is_empty() {
+            last_activity = Instant::now();
+        } else if last_activity.elapsed() >= idle_timeout {
+            close_websocket(&mut ws, CloseCode::Away, "idle timeout");
+            break;
+        }
+    }
+    // Dropping the client closes the WebSocket's session
+    Ok(())
+}
+
+/// Turns a browser's JSON message into an nREPL request in the socket's session.
+///
+/// # Returns
+///
+/// Returns the request in flight, or the browser's id and a reason to report
+/// if the message can't be relayed.
+fn relay_request(
+    client: &mut NreplClient,
+    in_flight: &[InFlight],
+    text: &str,
+) -> Result<InFlight, (Option<Json>, String)> {
+    let mut fields = match serde_json::from_str(text) {
+        Ok(Json::Object(fields)) => fields,
+        Ok(_) => return Err((None, "message must be a JSON object".to_string())),
+        Err(e) => return Err((None, format!("invalid JSON: {}", e))),
+    };
+    let browser_id = fields.remove("id");
+    fields.remove("session");
+
+    // Interrupts name the browser's id; the server only knows the bridge's
+    if let Some(target) = fields.get("interrupt-id").cloned()
+        && let Some(request) = in_flight
+            .iter()
+            .find(|request| request.browser_id.as_ref() == Some(&target))
+    {
+        fields.insert(
+            "interrupt-id".to_string(),
+            Json::String(request.pending.id().to_string()),
+        );
+    }
+
+    let msg: Message = fields
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, json_to_bencode(value)?)))
+        .collect();
+    let request = match RequestMessage::from_message(msg) {
+        Ok(request) => request,
+        Err(_) => return Err((browser_id, "message has no op".to_string())),
+    };
+    if matches!(request.request, Request::Clone | Request::Close) {
+        return Err((browser_id, "sessions are managed by the bridge".to_string()));
+    }
+    match client.send_session_request(request) {
+        Ok(pending) => Ok(InFlight {
+            browser_id,
+            pending,
+        }),
+        Err(e) => Err((browser_id, e.to_string())),
+    }
+}
+
+/// Sends every reply that has arrived and drops completed requests.
+///
+/// # Returns
+///
+/// Returns `Ok(false)` if the backend connection has been lost.
+fn forward_replies(
+    ws: &mut WebSocket<TcpStream>,
+    in_flight: &mut Vec<InFlight>,
+) -> io::Result<bool> {
+    for request in in_flight.iter_mut() {
+        while !request.pending.is_done() {
+            match request.pending.poll() {
+                Ok(Some(response)) => {
+                    let mut reply: Map<String, Json> = response
+                        .into_iter()
+                        .map(|(key, value)| (key, bencode_to_json(value)))
+                        .collect();
+                    match &request.browser_id {
+                        Some(id) => reply.insert("id".to_string(), id.clone()),
+                        None => reply.remove("id"),
+                    };
+                    send_json(ws, reply)?;
+                }
+                Ok(None) => break,
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+    in_flight.retain(|request| !request.pending.is_done());
+    Ok(true)
+}
+
+fn error_reply(browser_id: Option<Json>, error: &str) -> Map<String, Json> {
+    let mut reply = Map::new();
+    if let Some(id) = browser_id {
+        reply.insert("id".to_string(), id);
+    }
+    reply.insert("err".to_string(), Json::String(error.to_string()));
+    reply.insert(
+        "status".to_string(),
+        Json::Array(vec!["error".into(), "done".into()]),
+    );
+    reply
+}
+
+fn send_json(ws: &mut WebSocket<TcpStream>, reply: Map<String, Json>) -> io::Result<()> {
+    let text = Json::Object(reply).to_string();
+    ws.send(WsMessage::text(text)).map_err(io::Error::other)
+}
+
+fn close_websocket(ws: &mut WebSocket<TcpStream>, code: CloseCode, reason: &str) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    if ws.close(Some(frame)).is_err() {
+        return;
+    }
+    // Give the browser a moment to acknowledge the close
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while Instant::now() < deadline {
+        match ws.read() {
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// Converts JSON to bencode. Bencode has no null, so nulls are dropped;
+/// booleans become `"true"`/`"false"` and non-integer numbers their text.
+fn json_to_bencode(value: Json) -> Option<Value> {
+    Some(match value {
+        Json::Null => return None,
+        Json::Bool(b) => Value::Bytes(b.to_string().into_bytes()),
+        Json::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Bytes(n.to_string().into_bytes()),
+        },
+        Json::String(s) => Value::Bytes(s.into_bytes()),
+        Json::Array(items) => Value::List(items.into_iter().filter_map(json_to_bencode).collect()),
+        Json::Object(fields) => Value::Dict(
+            fields
+                .into_iter()
+                .filter_map(|(key, value)| Some((key.into_bytes(), json_to_bencode(value)?)))
+                .collect(),
+        ),
+    })
+}
+
+/// Converts bencode to JSON, decoding byte strings as UTF-8.
+fn bencode_to_json(value: Value) -> Json {
+    match value {
+        Value::Int(i) => Json::from(i),
+        Value::Bytes(bytes) => Json::String(String::from_utf8_lossy(&bytes).into_owned()),
+        Value::List(items) => Json::Array(items.into_iter().map(bencode_to_json).collect()),
+        Value::Dict(fields) => Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        String::from_utf8_lossy(&key).into_owned(),
+                        bencode_to_json(value),
+                    )
+                })
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+    use serde_json::json;
+    use tungstenite::client::IntoClientRequest;
+
+    type Browser = WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>;
+
+    fn start_bridge(server: &MockServer, idle_timeout: Duration) -> SocketAddr {
+        let bridge = WsBridge::bind("127.0.0.1:0", "127.0.0.1", server.port())
+            .unwrap()
+            .idle_timeout(idle_timeout);
+        let addr = bridge.local_addr().unwrap();
+        thread::spawn(move || bridge.run());
+        addr
+    }
+
+    fn open(addr: SocketAddr) -> Browser {
+        upgrade(&format!("ws://{}", addr), Some("http://localhost:8080")).unwrap()
+    }
+
+    fn upgrade(url: &str, origin: Option<&str>) -> Result<Browser, tungstenite::Error> {
+        let mut request = url.into_client_request()?;
+        if let Some(origin) = origin {
+            request
+                .headers_mut()
+                .insert("Origin", origin.parse().unwrap());
+        }
+        tungstenite::connect(request).map(|(browser, _)| browser)
+    }
+
+    fn status(result: Result<Browser, tungstenite::Error>) -> Option<StatusCode> {
+        match result {
+            Ok(_) => None,
+            Err(tungstenite::Error::Http(response)) => Some(response.status()),
+            Err(e) => panic!("expected an HTTP error, got {}", e),
+        }
+    }
+
+    /// Sends a request and collects the replies up to `done`.
+    fn request(browser: &mut Browser, message: Json) -> Vec<Json> {
+        browser.send(WsMessage::text(message.to_string())).unwrap();
+        let mut replies = Vec::new();
+        loop {
+            let text = browser.read().unwrap().into_text().unwrap();
+            let reply: Json = serde_json::from_str(text.as_str()).unwrap();
+            let done = reply["status"]
+                .as_array()
+                .is_some_and(|status| status.contains(&json!("done")));
+            replies.push(reply);
+            if done {
+                return replies;
+            }
+        }
+    }
+
+    #[test]
+    fn test_relays_json_messages_with_browser_ids() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(+ 1 2)", Reply::new().out("adding\n").value("3").done());
+        let mut browser = open(start_bridge(&server, Duration::from_secs(60)));
+
+        let replies = request(
+            &mut browser,
+            json!({"op": "eval", "code": "(+ 1 2)", "id": 7, "session": "ignored"}),
+        );
+        assert!(replies.iter().all(|reply| reply["id"] == json!(7)));
+        assert!(
+            replies
+                .iter()
+                .any(|reply| reply["out"] == json!("adding\n"))
+        );
+        assert!(replies.iter().any(|reply| reply["value"] == json!("3")));
+        let session = replies[0]["session"].as_str().unwrap();
+        assert_ne!(session, "ignored");
+        assert!(server.sessions().contains(&session.to_string()));
+
+        let replies = request(&mut browser, json!({"op": "close", "id": "x"}));
+        assert_eq!(replies[0]["status"], json!(["error", "done"]));
+    }
+
+    #[test]
+    fn test_each_websocket_has_its_own_session() {
+        let server = MockServer::start().unwrap();
+        let addr = start_bridge(&server, Duration::from_secs(60));
+        let mut first = open(addr);
+        let mut second = open(addr);
+
+        let describe = json!({"op": "describe", "id": "d"});
+        let first_session = request(&mut first, describe.clone())[0]["session"].clone();
+        let second_session = request(&mut second, describe)[0]["session"].clone();
+        assert_ne!(first_session, second_session);
+    }
+
+    #[test]
+    fn test_idle_websocket_is_closed_with_its_session() {
+        let server = MockServer::start().unwrap();
+        let mut browser = open(start_bridge(&server, Duration::from_millis(200)));
+        let replies = request(&mut browser, json!({"op": "describe", "id": 1}));
+        let session = replies[0]["session"].as_str().unwrap().to_string();
+
+        loop {
+            match browser.read() {
+                Ok(WsMessage::Close(frame)) => {
+                    assert_eq!(frame.unwrap().code, CloseCode::Away);
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => panic!("expected a close frame, got {}", e),
+            }
+        }
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while server.sessions().contains(&session) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!server.sessions().contains(&session));
+    }
+
+    #[test]
+    fn test_foreign_origin_is_forbidden() {
+        let server = MockServer::start().unwrap();
+        let url = format!("ws://{}", start_bridge(&server, Duration::from_secs(60)));
+
+        let foreign = upgrade(&url, Some("https://evil.example"));
+        assert_eq!(status(foreign), Some(StatusCode::FORBIDDEN));
+        assert_eq!(status(upgrade(&url, None)), Some(StatusCode::FORBIDDEN));
+        for origin in [
+            "http://127.0.0.1:3000",
+            "https://[::1]",
+            "http://LOCALHOST/",
+        ] {
+            assert_eq!(status(upgrade(&url, Some(origin))), None, "{}", origin);
+        }
+    }
+
+    #[test]
+    fn test_allowed_origins_replace_the_same_host_default() {
+        let server = MockServer::start().unwrap();
+        let bridge = WsBridge::bind("127.0.0.1:0", "127.0.0.1", server.port())
+            .unwrap()
+            .allowed_origins(["https://app.example/"]);
+        let url = format!("ws://{}", bridge.local_addr().unwrap());
+        thread::spawn(move || bridge.run());
+
+        assert_eq!(status(upgrade(&url, Some("https://app.example"))), None);
+        let local = upgrade(&url, Some("http://localhost:8080"));
+        assert_eq!(status(local), Some(StatusCode::FORBIDDEN));
+    }
+
+    #[test]
+    fn test_token_is_required_when_set() {
+        let server = MockServer::start().unwrap();
+        let bridge = WsBridge::bind("127.0.0.1:0", "127.0.0.1", server.port())
+            .unwrap()
+            .token("s3cret");
+        let url = format!("ws://{}", bridge.local_addr().unwrap());
+        thread::spawn(move || bridge.run());
+        let origin = Some("http://localhost");
+
+        assert_eq!(status(upgrade(&url, origin)), Some(StatusCode::FORBIDDEN));
+        let wrong = upgrade(&format!("{}/?token=guess", url), origin);
+        assert_eq!(status(wrong), Some(StatusCode::FORBIDDEN));
+        let right = upgrade(&format!("{}/?session=1&token=s3cret", url), origin);
+        assert_eq!(status(right), None);
+    }
+
+    #[test]
+    fn test_token_is_percent_decoded() {
+        let server = MockServer::start().unwrap();
+        let bridge = WsBridge::bind("127.0.0.1:0", "127.0.0.1", server.port())
+            .unwrap()
+            .token("a+b=c&d%e");
+        let url = format!("ws://{}", bridge.local_addr().unwrap());
+        thread::spawn(move || bridge.run());
+        let origin = Some("http://localhost");
+
+        let encoded = upgrade(&format!("{}/?token=a%2Bb%3Dc%26d%25e", url), origin);
+        assert_eq!(status(encoded), None);
+        let truncated = upgrade(&format!("{}/?token=a%2Bb%3Dc", url), origin);
+        assert_eq!(status(truncated), Some(StatusCode::FORBIDDEN));
+        let malformed = upgrade(&format!("{}/?token=a%2", url), origin);
+        assert_eq!(status(malformed), Some(StatusCode::FORBIDDEN));
+    }
+}
@@ -0,0 +1,201 @@
+use crate::bencode::DEFAULT_MAX_MESSAGE_SIZE;
+use crate::client::{Codec, NreplClient, NreplError};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+use crate::transport::Endpoint;
+use std::path::Path;
+use std::time::Duration;
+
+/// Connection-level settings, kept by the client so reconnects use them too.
+#[derive(Debug, Clone)]
+pub(crate) struct SocketSettings {
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) keepalive: bool,
+    pub(crate) keepalive_idle: Option<Duration>,
+    pub(crate) keepalive_interval: Option<Duration>,
+    pub(crate) keepalive_count: Option<u32>,
+    pub(crate) nodelay: bool,
+    pub(crate) max_message_size: usize,
+    pub(crate) codec: Codec,
+}
+
+impl Default for SocketSettings {
+    fn default() -> Self {
+        SocketSettings {
+            connect_timeout: None,
+            keepalive: true,
+            keepalive_idle: None,
+            keepalive_interval: None,
+            keepalive_count: None,
+            nodelay: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            codec: Codec::Bencode,
+        }
+    }
+}
+
+/// Configures and opens an [`NreplClient`].
+///
+/// `NreplClient::connect` is the same as `NreplClientBuilder::new().connect`.
+///
+/// ```no_run
+/// use nrepl_client_server_demo::builder::NreplClientBuilder;
+/// use std::time::Duration;
+///
+/// let client = NreplClientBuilder::new()
+///     .connect_timeout(Duration::from_secs(2))
+///     .keepalive_idle(Duration::from_secs(30))
+///     .nodelay(true)
+///     .max_message_size(16 * 1024 * 1024)
+///     .connect("127.0.0.1", 7888);
+/// ```
+#[derive(Debug, Clone)]
+pub struct NreplClientBuilder {
+    pub(crate) socket: SocketSettings,
+    pub(crate) read_timeout: Duration,
+    pub(crate) write_timeout: Duration,
+    pub(crate) eval_timeout: Duration,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>,
+}
+
+impl Default for NreplClientBuilder {
+    fn default() -> Self {
+        NreplClientBuilder {
+            socket: SocketSettings::default(),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            eval_timeout: Duration::from_secs(60),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl NreplClientBuilder {
+    /// Creates a builder with the defaults `NreplClient::connect` uses: no
+    /// connect timeout, 30s read, 10s write and 60s eval timeouts, keepalive on
+    /// with system intervals, Nagle's algorithm on, and a 1 MB message limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits how long establishing the TCP connection may take.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.socket.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long single-response requests such as `describe` wait.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a blocked write may take.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for `eval`, `eval_in_ns`, `eval_in_session` and
+    /// `load_file`, which take no timeout of their own.
+    pub fn eval_timeout(mut self, timeout: Duration) -> Self {
+        self.eval_timeout = timeout;
+        self
+    }
+
+    /// Turns TCP keepalive on or off.
+    pub fn keepalive(mut self, enabled: bool) -> Self {
+        self.socket.keepalive = enabled;
+        self
+    }
+
+    /// Sets how long the connection may be idle before keepalive probes start.
+    pub fn keepalive_idle(mut self, idle: Duration) -> Self {
+        self.socket.keepalive_idle = Some(idle);
+        self
+    }
+
+    /// Sets the time between unanswered keepalive probes.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.socket.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets how many unanswered probes close the connection.
+    pub fn keepalive_count(mut self, count: u32) -> Self {
+        self.socket.keepalive_count = Some(count);
+        self
+    }
+
+    /// Sets `TCP_NODELAY`, sending small requests without waiting to batch them.
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.socket.nodelay = enabled;
+        self
+    }
+
+    /// Sets the largest encoded response the client accepts, in bytes.
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.socket.max_message_size = bytes;
+        self
+    }
+
+    /// Sets the wire format, which must match the server's `--transport`.
+    /// Defaults to bencode.
+    pub fn codec(mut self, codec: Codec) -> Self {
+        self.socket.codec = codec;
+        self
+    }
+
+    /// Connects over TLS with the given certificates. Only applies to
+    /// [`connect`](Self::connect); Unix sockets are always plaintext.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Connects to an nREPL server with these settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The hostname or IP address of the nREPL server.
+    /// * `port` - The port number of the nREPL server.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the connected `NreplClient`, or an
+    /// `NreplError` if connecting fails. `NreplError::LimitExceeded` names
+    /// `connect_timeout` if the connection could not be made in time.
+    pub fn connect(&self, host: &str, port: u16) -> Result<NreplClient, NreplError> {
+        let address = format!("{}:{}", host, port);
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let endpoint = Endpoint::Tls {
+                address,
+                server_name: tls.server_name_for(host)?,
+                config: tls.client_config()?,
+            };
+            return NreplClient::connect_with(self, endpoint);
+        }
+        NreplClient::connect_with(self, Endpoint::Tcp(address))
+    }
+
+    /// Connects to an nREPL server on a Unix domain socket with these settings.
+    ///
+    /// The TCP-only settings (connect timeout, keepalive and nodelay) don't
+    /// apply and are ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the socket file.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the connected `NreplClient`, or an
+    /// `NreplError` if connecting fails.
+    pub fn connect_unix<P: AsRef<Path>>(&self, path: P) -> Result<NreplClient, NreplError> {
+        NreplClient::connect_with(self, Endpoint::Unix(path.as_ref().to_path_buf()))
+    }
+}
@@ -1,6 +1,31 @@
+use crate::bencode::Message;
+use crate::builder::{NreplClientBuilder, SocketSettings};
+pub use crate::codec::Codec;
+use crate::codec::MessageDecoder;
+use crate::completion::completions_from_responses;
+pub use crate::completion::{Completion, CompletionKind};
+pub use crate::describe::{OpInfo, ServerDescription, Version, Versions};
+use crate::edn;
+pub use crate::eval::{
+    EvalEvent, EvalException, EvalOptions, EvalResult, EvalStream, EvalValue, StackFrame,
+};
+use crate::eval::{NsTracker, StdinResponder};
+pub use crate::lookup::SymbolInfo;
+use crate::message::{Request, RequestMessage, Response, Status};
+pub use crate::reconnect::ReconnectPolicy;
+use crate::routing::{self, Route, Routed};
+pub use crate::transport::Transport;
+use crate::transport::{Endpoint, SharedWriter};
+use serde::de::DeserializeOwned;
+use serde_bencode::value::Value;
+use socket2::{SockRef, TcpKeepalive};
 use std::collections::HashMap;
 use std::io::{ErrorKind, Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
 use std::time::{Duration, Instant};
 
 /// A client for interacting with an nREPL server over TCP.
@@ -8,37 +33,81 @@ use std::time::{Duration, Instant};
 /// `NreplClient` manages the connection, session, and communication
 /// with an nREPL server, providing methods to evaluate code,
 /// manage sessions, and handle timeouts.
+///
+/// Responses are read by a background thread and routed to the request
+/// that sent them by `id`, so several requests can be in flight at once.
 pub struct NreplClient {
-    stream: TcpStream,
+    /// Where the client connected to, used when reconnecting.
+    endpoint: Endpoint,
+    stream: Box<dyn Transport>,
+    /// Sessions created with a name, mapped to their server IDs.
+    named_sessions: HashMap<String, String>,
+    /// Write half shared with eval streams so they can answer `need-input`.
+    writer: SharedWriter,
+    stdin_provider: Arc<Mutex<Option<StdinProvider>>>,
     session: Option<String>,
+    /// Last namespace reported by an eval, keyed by session ID.
+    namespaces: Arc<Mutex<HashMap<String, String>>>,
+    /// The last `describe` reply, used to check which ops the server supports.
+    description: Option<ServerDescription>,
     read_timeout: Duration,
     write_timeout: Duration,
+    /// Timeout for eval methods that don't take one.
+    eval_timeout: Duration,
+    socket_settings: SocketSettings,
+    /// Whether evals that time out are interrupted on the server.
+    interrupt_on_timeout: bool,
+    reconnect_policy: Option<ReconnectPolicy>,
+    /// Set while a reconnect is in progress so its own requests don't start another.
+    reconnecting: bool,
+    router: Arc<Router>,
+    reader: Option<JoinHandle<()>>,
+    heartbeat: Option<Heartbeat>,
 }
 
-pub struct EvalResult {
-    pub value: Option<String>,
-    pub output: String,
-    pub error: String,
-    pub has_error: bool,
+/// An open socket together with the reader thread serving it.
+struct Connection {
+    stream: Box<dyn Transport>,
+    writer: SharedWriter,
+    router: Arc<Router>,
+    reader: JoinHandle<()>,
 }
 
-impl Default for EvalResult {
-    fn default() -> Self {
-        EvalResult {
-            value: None,
-            output: String::new(),
-            error: String::new(),
-            has_error: false,
-        }
-    }
+/// Supplies input when evaluated code reads from `*in*`.
+///
+/// Called once per `need-input` status. Returning `None` signals end of input,
+/// so `read-line` returns `nil`.
+pub type StdinProvider = Box<dyn FnMut() -> Option<String> + Send>;
+
+/// How the server answered an `interrupt` request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterruptOutcome {
+    /// The evaluation was interrupted; its own responses end with `interrupted`.
+    Interrupted,
+    /// Nothing was running in the session.
+    SessionIdle,
+    /// Something else is running in the session; it was left alone.
+    IdMismatch,
 }
 
 #[derive(Debug)]
 pub enum NreplError {
     ConnectionClosed,
+    /// A timeout passed to the call ran out. Waits bounded by a client
+    /// setting report `LimitExceeded` instead.
     Timeout,
     ParseError(String),
     IoError(std::io::Error),
+    /// A configured limit was hit; `setting` names the builder setting.
+    LimitExceeded {
+        setting: &'static str,
+        limit: String,
+    },
+    /// The connection dropped and was re-established. Sessions were recreated
+    /// empty, so definitions and bindings from before are gone.
+    Reconnected,
+    /// The server does not handle the named op.
+    UnsupportedOp(String),
     Other(String),
 }
 
@@ -49,6 +118,15 @@ impl std::fmt::Display for NreplError {
             NreplError::Timeout => write!(f, "Operation timed out"),
             NreplError::ParseError(msg) => write!(f, "Parse error: {}", msg),
             NreplError::IoError(e) => write!(f, "IO error: {}", e),
+            NreplError::LimitExceeded { setting, limit } => {
+                write!(f, "{} exceeded (limit {})", setting, limit)
+            }
+            NreplError::Reconnected => {
+                write!(f, "Reconnected to server; session state was lost")
+            }
+            NreplError::UnsupportedOp(op) => {
+                write!(f, "Server does not support the '{}' op", op)
+            }
             NreplError::Other(msg) => write!(f, "Error: {}", msg),
         }
     }
@@ -62,432 +140,1720 @@ impl From<std::io::Error> for NreplError {
     }
 }
 
-impl NreplClient {
-    /// Connects to an nREPL server at the given host and port.
-    ///
-    /// # Arguments
-    ///
-    /// * `host` - The hostname or IP address of the nREPL server.
-    /// * `port` - The port number of the nREPL server.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` containing a new `NreplClient` if successful,
-    /// or an `NreplError` if the connection fails.
-    pub fn connect(host: &str, port: u16) -> Result<Self, NreplError> {
-        let stream = TcpStream::connect(format!("{}:{}", host, port))?;
+/// Prints each frame of the session's last exception on its own line.
+const STACKTRACE_EVAL: &str =
+    "(when-let [e *e] (doseq [frame (.getStackTrace e)] (println (str frame))))";
 
-        // Set timeouts
-        stream.set_read_timeout(Some(Duration::from_secs(30)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(10)))?;
+/// Routes responses to the requests waiting on them, by message `id`.
+type Router = routing::Router<Sender<Routed>>;
 
-        // Enable TCP keepalive to detect dropped connections
-        #[cfg(unix)]
-        {
-            use std::os::unix::io::AsRawFd;
-            unsafe {
-                let fd = stream.as_raw_fd();
-                let keepalive: libc::c_int = 1;
-                libc::setsockopt(
-                    fd,
-                    libc::SOL_SOCKET,
-                    libc::SO_KEEPALIVE,
-                    &keepalive as *const _ as *const libc::c_void,
-                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
-                );
-            }
-        }
+/// A handle to a request that has been sent but may not have completed yet.
+///
+/// Responses are delivered in the order the server sent them. The request is
+/// complete once a response carrying the `done` status has been received.
+/// Dropping the handle stops routing further responses for this request.
+pub struct PendingRequest {
+    route: Route<Sender<Routed>>,
+    session: Option<String>,
+    receiver: Receiver<Routed>,
+}
 
-        Ok(NreplClient {
-            stream,
-            session: None,
-            read_timeout: Duration::from_secs(30),
-            write_timeout: Duration::from_secs(10),
-        })
+impl PendingRequest {
+    /// Returns the message `id` this request was sent with.
+    pub fn id(&self) -> &str {
+        self.route.id()
     }
 
-    /// Sets the read and write timeouts for the client connection.
+    /// Returns the session this request was sent in, if any.
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// Returns `true` once the `done` response for this request has been received.
+    pub fn is_done(&self) -> bool {
+        self.route.is_done()
+    }
+
+    /// Waits for the next response to this request.
     ///
     /// # Arguments
     ///
-    /// * `read_timeout` - Duration for read timeout.
-    /// * `write_timeout` - Duration for write timeout.
+    /// * `timeout` - The maximum duration to wait for a response.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if successful, or an `NreplError` if setting timeouts fails.
-    pub fn set_timeouts(
-        &mut self,
-        read_timeout: Duration,
-        write_timeout: Duration,
-    ) -> Result<(), NreplError> {
-        self.stream.set_read_timeout(Some(read_timeout))?;
-        self.stream.set_write_timeout(Some(write_timeout))?;
-        self.read_timeout = read_timeout;
-        self.write_timeout = write_timeout;
-        Ok(())
+    /// Returns a `Result` containing the next response message,
+    /// or an `NreplError` if the wait times out, the request has already
+    /// completed, or the connection is lost.
+    pub fn next_response(&mut self, timeout: Duration) -> Result<Message, NreplError> {
+        self.route.ensure_not_done()?;
+        match self.receiver.recv_timeout(timeout) {
+            Ok(response) => self.route.observe(response),
+            Err(RecvTimeoutError::Timeout) => Err(NreplError::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(NreplError::ConnectionClosed),
+        }
     }
 
-    /// Creates a new session on the nREPL server and updates the client session.
+    /// Returns the next response if one has already arrived, without blocking.
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing the new session ID as a `String` if successful,
-    /// or an `NreplError` if the operation fails.
-    pub fn clone_session(&mut self) -> Result<String, NreplError> {
-        let mut msg = HashMap::new();
-        msg.insert(
-            "op".to_string(),
-            serde_bencode::value::Value::Bytes(b"clone".to_vec()),
-        );
-        msg.insert(
-            "id".to_string(),
-            serde_bencode::value::Value::Bytes(uuid::Uuid::new_v4().to_string().into_bytes()),
-        );
-
-        self.send_message(&msg)?;
-        let response = self.read_message_with_timeout()?;
-
-        if let Some(new_session) = response.get("new-session") {
-            if let serde_bencode::value::Value::Bytes(session_bytes) = new_session {
-                let session_id = String::from_utf8_lossy(session_bytes).to_string();
-                self.session = Some(session_id.clone());
-                return Ok(session_id);
-            }
+    /// Returns `Ok(Some(message))` if a response was waiting, `Ok(None)` if
+    /// nothing has arrived yet, or an `NreplError` if the request has already
+    /// completed or the connection is lost.
+    pub fn poll(&mut self) -> Result<Option<Message>, NreplError> {
+        self.route.ensure_not_done()?;
+        match self.receiver.try_recv() {
+            Ok(response) => self.route.observe(response).map(Some),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(NreplError::ConnectionClosed),
         }
-
-        Err(NreplError::Other(
-            "Failed to get session from clone response".to_string(),
-        ))
     }
 
-    /// Evaluates the given Clojure code on the nREPL server with a default timeout.
+    /// Collects every response to this request until it completes.
     ///
     /// # Arguments
     ///
-    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum total duration to wait for completion.
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing an `EvalResult` if successful,
-    /// or an `NreplError` if the evaluation fails.
-    pub fn eval(&mut self, code: &str) -> Result<EvalResult, NreplError> {
-        self.eval_with_timeout(code, Duration::from_secs(60))
+    /// Returns a `Result` containing all responses, ending with the `done` one,
+    /// or an `NreplError` if the request times out or the connection is lost.
+    pub fn wait(&mut self, timeout: Duration) -> Result<Vec<Message>, NreplError> {
+        let deadline = Instant::now() + timeout;
+        let mut responses = Vec::new();
+        while !self.is_done() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            responses.push(self.next_response(remaining)?);
+        }
+        Ok(responses)
     }
 
-    /// Evaluates the given Clojure code on the nREPL server with a custom timeout.
-    ///
-    /// # Arguments
-    ///
-    /// * `code` - The Clojure code to evaluate.
-    /// * `timeout` - The maximum duration to wait for evaluation.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result` containing an `EvalResult` if successful,
-    /// or an `NreplError` if the evaluation fails or times out.
-    pub fn eval_with_timeout(
-        &mut self,
-        code: &str,
+    /// Stops waiting for this request. Responses that arrive later are discarded.
+    pub fn cancel(self) {}
+}
+
+/// A background thread that pings the server and shuts the connection down
+/// when a ping goes unanswered.
+struct Heartbeat {
+    interval: Duration,
+    timeout: Duration,
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    thread: JoinHandle<()>,
+}
+
+impl Heartbeat {
+    fn start(
+        writer: SharedWriter,
+        codec: Codec,
+        router: Arc<Router>,
+        stream: Box<dyn Transport>,
+        interval: Duration,
         timeout: Duration,
-    ) -> Result<EvalResult, NreplError> {
-        // ...
-        // Ensure there a session already otherwise create new
-        if self.session.is_none() {
-            self.clone_session()?;
-        }
+    ) -> Result<Self, NreplError> {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stopped = Arc::clone(&stopped);
+        let thread = thread::Builder::new()
+            .name("nrepl-heartbeat".to_string())
+            .spawn(move || {
+                let (lock, wakeup) = &*thread_stopped;
+                loop {
+                    let (stopped, _) = wakeup
+                        .wait_timeout_while(lock.lock().unwrap(), interval, |stopped| !*stopped)
+                        .unwrap();
+                    if *stopped || router.is_closed() {
+                        return;
+                    }
+                    drop(stopped);
 
-        let mut msg = HashMap::new();
-        let eval_id = uuid::Uuid::new_v4().to_string();
-        msg.insert(
-            "op".to_string(),
-            serde_bencode::value::Value::Bytes(b"eval".to_vec()),
-        );
-        msg.insert(
-            "id".to_string(),
-            serde_bencode::value::Value::Bytes(eval_id.clone().into_bytes()),
-        );
-        msg.insert(
-            "code".to_string(),
-            serde_bencode::value::Value::Bytes(code.as_bytes().to_vec()),
-        );
+                    if !heartbeat_ping(&writer, codec, &router, timeout) {
+                        // Unblocks the reader thread, which fails every pending request
+                        let _ = stream.shutdown_transport();
+                        return;
+                    }
+                }
+            })?;
 
-        if let Some(session) = &self.session {
-            msg.insert(
-                "session".to_string(),
-                serde_bencode::value::Value::Bytes(session.as_bytes().to_vec()),
-            );
+        Ok(Heartbeat {
+            interval,
+            timeout,
+            stopped,
+            thread,
+        })
+    }
+
+    /// Stops the thread and returns its settings.
+    fn stop(self) -> (Duration, Duration) {
+        let (lock, wakeup) = &*self.stopped;
+        *lock.lock().unwrap() = true;
+        wakeup.notify_all();
+        let _ = self.thread.join();
+        (self.interval, self.timeout)
+    }
+}
+
+/// Sends one `describe` and reports whether its reply arrived in time.
+fn heartbeat_ping(
+    writer: &Mutex<Box<dyn Transport>>,
+    codec: Codec,
+    router: &Router,
+    timeout: Duration,
+) -> bool {
+    let id = uuid::Uuid::new_v4().to_string();
+    let Ok(receiver) = router.register(&id) else {
+        return false;
+    };
+
+    let mut request = RequestMessage::new(Request::Describe);
+    request.id = Some(id.clone());
+    let answered = write_message(writer, codec, &request.to_message()).is_ok()
+        && matches!(receiver.recv_timeout(timeout), Ok(Ok(_)));
+    router.unregister(&id);
+    answered
+}
+
+/// Connects to `endpoint` and starts a reader thread for the new connection.
+fn open_connection(
+    endpoint: &Endpoint,
+    settings: &SocketSettings,
+    write_timeout: Duration,
+) -> Result<Connection, NreplError> {
+    let stream: Box<dyn Transport> = match endpoint {
+        Endpoint::Tcp(address) => {
+            let stream = connect_stream(address, settings.connect_timeout)?;
+            configure_tcp(&stream, settings)?;
+            Box::new(stream)
         }
+        #[cfg(feature = "tls")]
+        Endpoint::Tls {
+            address,
+            server_name,
+            config,
+        } => {
+            let stream = connect_stream(address, settings.connect_timeout)?;
+            configure_tcp(&stream, settings)?;
+            let handshake_timeout = settings.connect_timeout.unwrap_or(write_timeout);
+            Box::new(crate::tls::TlsStream::connect(
+                stream,
+                server_name.clone(),
+                Arc::clone(config),
+                handshake_timeout,
+            )?)
+        }
+        #[cfg(unix)]
+        Endpoint::Unix(path) => Box::new(std::os::unix::net::UnixStream::connect(path)?),
+        #[cfg(not(unix))]
+        Endpoint::Unix(_) => {
+            return Err(NreplError::IoError(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )));
+        }
+    };
+    // The reader thread blocks until data arrives or the connection is shut
+    // down; waiting for responses is bounded per request.
+    stream.set_transport_write_timeout(Some(write_timeout))?;
 
-        self.send_message(&msg)?;
+    let router = Arc::new(Router::new());
+    let writer = Arc::new(Mutex::new(stream.try_clone_transport()?));
+    let reader_stream = stream.try_clone_transport()?;
+    let reader_router = Arc::clone(&router);
+    let decoder = settings.codec.decoder(settings.max_message_size);
+    let reader = thread::Builder::new()
+        .name("nrepl-reader".to_string())
+        .spawn(move || read_loop(reader_stream, reader_router, decoder))?;
 
-        let mut result = EvalResult::default();
-        let start_time = Instant::now();
+    Ok(Connection {
+        stream,
+        writer,
+        router,
+        reader,
+    })
+}
 
-        // Keep reading responses until we get "done" status or timeout
-        loop {
-            if start_time.elapsed() > timeout {
-                return Err(NreplError::Timeout);
-            }
+/// Applies the TCP-only settings: nodelay and keepalive.
+fn configure_tcp(stream: &TcpStream, settings: &SocketSettings) -> Result<(), NreplError> {
+    stream.set_read_timeout(None)?;
+    stream.set_nodelay(settings.nodelay)?;
 
-            let response = match self.read_message_with_timeout() {
-                Ok(resp) => resp,
-                Err(NreplError::ConnectionClosed) => {
-                    return Err(NreplError::ConnectionClosed);
-                }
-                Err(e) => return Err(e),
-            };
-
-            // Verify this response is for our request
-            if let Some(serde_bencode::value::Value::Bytes(id_bytes)) = response.get("id") {
-                let response_id = String::from_utf8_lossy(id_bytes);
-                if response_id != eval_id {
-                    continue; // Skip responses for other requests
-                }
+    // TCP keepalive detects connections that died without being closed
+    let socket = SockRef::from(stream);
+    socket.set_keepalive(settings.keepalive)?;
+    if settings.keepalive {
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(idle) = settings.keepalive_idle {
+            keepalive = keepalive.with_time(idle);
+        }
+        // Probe interval and count can't be set on every platform
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        ))]
+        {
+            if let Some(interval) = settings.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
             }
-
-            // Extract value
-            if let Some(serde_bencode::value::Value::Bytes(value_bytes)) = response.get("value") {
-                result.value = Some(String::from_utf8_lossy(value_bytes).to_string());
+            if let Some(count) = settings.keepalive_count {
+                keepalive = keepalive.with_retries(count);
             }
+        }
+        socket.set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
 
-            // Extract stdout
-            if let Some(serde_bencode::value::Value::Bytes(out_bytes)) = response.get("out") {
-                result.output.push_str(&String::from_utf8_lossy(out_bytes));
-            }
+/// Opens the TCP connection, trying each resolved address within `timeout`.
+fn connect_stream(address: &str, timeout: Option<Duration>) -> Result<TcpStream, NreplError> {
+    let Some(timeout) = timeout else {
+        return Ok(TcpStream::connect(address)?);
+    };
 
-            // Extract stderr
-            if let Some(serde_bencode::value::Value::Bytes(err_bytes)) = response.get("err") {
-                result.error.push_str(&String::from_utf8_lossy(err_bytes));
+    let mut last_error = None;
+    for addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                last_error = Some(NreplError::LimitExceeded {
+                    setting: "connect_timeout",
+                    limit: format!("{:?}", timeout),
+                });
             }
+            Err(e) => last_error = Some(NreplError::IoError(e)),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        NreplError::IoError(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("No addresses found for {}", address),
+        ))
+    }))
+}
 
-            // Check status
-            if let Some(serde_bencode::value::Value::List(status_list)) = response.get("status") {
-                let mut is_done = false;
-                for status_item in status_list {
-                    if let serde_bencode::value::Value::Bytes(status_bytes) = status_item {
-                        let status_str = String::from_utf8_lossy(status_bytes);
-                        match status_str.as_ref() {
-                            "done" => is_done = true,
-                            "error" => result.has_error = true,
-                            _ => {}
-                        }
-                    }
-                }
-                if is_done {
-                    break;
-                }
+/// Reads messages from the server and hands them to the router until the
+/// connection closes or a malformed message is received.
+///
+/// After a decode error the rest of the stream can't be read, so the
+/// connection is shut down rather than left for the server to fill.
+fn read_loop(
+    mut stream: Box<dyn Transport>,
+    router: Arc<Router>,
+    mut decoder: Box<dyn MessageDecoder>,
+) {
+    let mut temp_buffer = [0u8; 4096];
+
+    let error = loop {
+        match decoder.next_message() {
+            Ok(Some(message)) => {
+                router.dispatch(message);
+                continue;
             }
+            Ok(None) => {}
+            Err(e) => {
+                // Writers fail at once instead of waiting on a socket nobody drains
+                let _ = stream.shutdown_transport();
+                break e;
+            }
+        }
+
+        match stream.read(&mut temp_buffer) {
+            Ok(0) => break NreplError::ConnectionClosed,
+            Ok(n) => decoder.feed(&temp_buffer[..n]),
+            Err(e) => match e.kind() {
+                ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut => {}
+                ErrorKind::UnexpectedEof
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::ConnectionReset => break NreplError::ConnectionClosed,
+                _ => break NreplError::IoError(e),
+            },
         }
+    };
+
+    router.fail_all(&error);
+}
 
-        Ok(result)
+/// Builds a stack frame from a frame dict sent by the `stacktrace` op.
+fn frame_from_dict(fields: &HashMap<Vec<u8>, Value>) -> StackFrame {
+    let text = |key: &str| match fields.get(key.as_bytes()) {
+        Some(Value::Bytes(bytes)) => Some(String::from_utf8_lossy(bytes).to_string()),
+        _ => None,
+    };
+    StackFrame {
+        name: text("name").unwrap_or_default(),
+        file: text("file"),
+        line: match fields.get(b"line".as_slice()) {
+            Some(Value::Int(line)) => Some(*line),
+            _ => None,
+        },
     }
+}
 
-    /// Requests a description of the nREPL server's capabilities and operations.
+/// Returns the given field of a message as a string, if it is a byte string.
+pub(crate) fn string_field(message: &Message, key: &str) -> Option<String> {
+    match message.get(key) {
+        Some(Value::Bytes(bytes)) => Some(String::from_utf8_lossy(bytes).to_string()),
+        _ => None,
+    }
+}
+
+/// Returns `true` if the message's `status` list contains the given status.
+pub(crate) fn has_status(message: &Message, status: &str) -> bool {
+    match message.get("status") {
+        Some(Value::List(items)) => items
+            .iter()
+            .any(|item| matches!(item, Value::Bytes(bytes) if bytes == status.as_bytes())),
+        _ => false,
+    }
+}
+
+/// Reports a timeout as the client setting that set its limit, so callers can
+/// tell which one to raise.
+fn timed_out(setting: &'static str, limit: Duration) -> impl FnOnce(NreplError) -> NreplError {
+    move |error| match error {
+        NreplError::Timeout => NreplError::LimitExceeded {
+            setting,
+            limit: format!("{:?}", limit),
+        },
+        error => error,
+    }
+}
+
+/// Encodes a message and writes it to the shared write half of the connection.
+pub(crate) fn write_message(
+    writer: &Mutex<Box<dyn Transport>>,
+    codec: Codec,
+    msg: &Message,
+) -> Result<(), NreplError> {
+    let encoded = codec.encode(msg)?;
+
+    let mut stream = writer.lock().unwrap();
+    match stream.write_all(&encoded).and_then(|_| stream.flush()) {
+        Ok(_) => Ok(()),
+        Err(e) => match e.kind() {
+            ErrorKind::BrokenPipe | ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset => {
+                Err(NreplError::ConnectionClosed)
+            }
+            _ => Err(NreplError::IoError(e)),
+        },
+    }
+}
+
+impl NreplClient {
+    /// Connects to an nREPL server at the given host and port.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The hostname or IP address of the nREPL server.
+    /// * `port` - The port number of the nREPL server.
     ///
     /// # Returns
     ///
-    /// Returns a `Result` containing a `HashMap` of server information if successful,
-    /// or an `NreplError` if the operation fails.
-    pub fn describe(&mut self) -> Result<HashMap<String, serde_bencode::value::Value>, NreplError> {
-        let mut msg = HashMap::new();
-        msg.insert(
-            "op".to_string(),
-            serde_bencode::value::Value::Bytes(b"describe".to_vec()),
-        );
-        msg.insert(
-            "id".to_string(),
-            serde_bencode::value::Value::Bytes(uuid::Uuid::new_v4().to_string().into_bytes()),
-        );
+    /// Returns a `Result` containing a new `NreplClient` if successful,
+    /// or an `NreplError` if the connection fails.
+    pub fn connect(host: &str, port: u16) -> Result<Self, NreplError> {
+        NreplClientBuilder::new().connect(host, port)
+    }
 
-        self.send_message(&msg)?;
-        self.read_message_with_timeout()
+    /// Connects to an nREPL server listening on a Unix domain socket.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the socket file, as passed to nREPL's `--socket`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a new `NreplClient` if successful,
+    /// or an `NreplError` if the connection fails.
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> Result<Self, NreplError> {
+        NreplClientBuilder::new().connect_unix(path)
     }
 
-    /// Sends an interrupt request to the nREPL server for the current session.
+    /// Connects to an nREPL server over TLS.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - The hostname or IP address of the nREPL server, which its
+    ///   certificate must be valid for unless `tls` names another.
+    /// * `port` - The port number of the nREPL server.
+    /// * `tls` - The CA to trust and the client certificate to present.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the interrupt was sent successfully,
-    /// or an `NreplError` if the operation fails.
-    pub fn interrupt(&mut self) -> Result<(), NreplError> {
-        if let Some(session) = &self.session.clone() {
-            let mut msg = HashMap::new();
-            msg.insert(
-                "op".to_string(),
-                serde_bencode::value::Value::Bytes(b"interrupt".to_vec()),
-            );
-            msg.insert(
-                "id".to_string(),
-                serde_bencode::value::Value::Bytes(uuid::Uuid::new_v4().to_string().into_bytes()),
-            );
-            msg.insert(
-                "session".to_string(),
-                serde_bencode::value::Value::Bytes(session.as_bytes().to_vec()),
-            );
+    /// Returns a `Result` containing a new `NreplClient` if successful,
+    /// or an `NreplError` if the connection or TLS handshake fails.
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(
+        host: &str,
+        port: u16,
+        tls: crate::tls::TlsConfig,
+    ) -> Result<Self, NreplError> {
+        NreplClientBuilder::new().tls(tls).connect(host, port)
+    }
 
-            self.send_message(&msg)?;
-            let _response = self.read_message_with_timeout()?;
-        }
-        Ok(())
+    /// Returns a builder for configuring timeouts and socket options before connecting.
+    pub fn builder() -> NreplClientBuilder {
+        NreplClientBuilder::new()
     }
 
-    /// Checks if the client is still connected to the nREPL server.
+    /// Connects using the settings collected by an `NreplClientBuilder`.
+    pub(crate) fn connect_with(
+        builder: &NreplClientBuilder,
+        endpoint: Endpoint,
+    ) -> Result<Self, NreplError> {
+        let connection = open_connection(&endpoint, &builder.socket, builder.write_timeout)?;
+
+        Ok(NreplClient {
+            endpoint,
+            stream: connection.stream,
+            writer: connection.writer,
+            stdin_provider: Arc::new(Mutex::new(None)),
+            named_sessions: HashMap::new(),
+            session: None,
+            namespaces: Arc::new(Mutex::new(HashMap::new())),
+            description: None,
+            read_timeout: builder.read_timeout,
+            write_timeout: builder.write_timeout,
+            eval_timeout: builder.eval_timeout,
+            socket_settings: builder.socket.clone(),
+            interrupt_on_timeout: false,
+            reconnect_policy: None,
+            reconnecting: false,
+            router: connection.router,
+            reader: Some(connection.reader),
+            heartbeat: None,
+        })
+    }
+
+    /// Sets how the client reconnects after the connection drops.
+    ///
+    /// With a policy set, the first request after a drop reconnects, recreates
+    /// the client's sessions, replays the policy's init script and then fails
+    /// with `NreplError::Reconnected` so the caller knows session state was
+    /// lost. An eval interrupted by the drop fails the same way. Requests are
+    /// not retried automatically. Without a policy (the default), a dropped
+    /// connection stays closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy to use, or `None` to disable reconnecting.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect_policy = policy;
+    }
+
+    /// Opens a new connection to the same server, replacing the current one.
+    ///
+    /// Pending requests on the old connection fail. The active session and
+    /// every named session are recreated empty under the same names, and the
+    /// reconnect policy's init script is evaluated in each.
     ///
     /// # Returns
     ///
-    /// Returns `true` if the connection is alive, `false` otherwise.
-    pub fn is_connected(&mut self) -> bool {
-        // Try to send a small describe message to check connection
-        let mut msg = HashMap::new();
-        msg.insert(
-            "op".to_string(),
-            serde_bencode::value::Value::Bytes(b"describe".to_vec()),
-        );
-        msg.insert(
-            "id".to_string(),
-            serde_bencode::value::Value::Bytes(uuid::Uuid::new_v4().to_string().into_bytes()),
-        );
-
-        match self.send_message(&msg) {
-            Ok(_) => {
-                // Try to read response
-                match self.read_message_with_timeout() {
-                    Ok(_) => true,
-                    Err(_) => false,
-                }
-            }
-            Err(_) => false,
-        }
+    /// Returns `Ok(())` once reconnected, or the last `NreplError` if every
+    /// attempt allowed by the policy (one attempt without a policy) failed.
+    pub fn reconnect(&mut self) -> Result<(), NreplError> {
+        let policy = self.reconnect_policy.clone().unwrap_or(ReconnectPolicy {
+            max_attempts: 1,
+            ..ReconnectPolicy::default()
+        });
+
+        self.reconnecting = true;
+        let result = self.reopen(&policy);
+        self.reconnecting = false;
+        result
     }
 
-    fn send_message(
-        &mut self,
-        msg: &HashMap<String, serde_bencode::value::Value>,
-    ) -> Result<(), NreplError> {
-        let encoded =
-            serde_bencode::to_bytes(msg).map_err(|e| NreplError::ParseError(e.to_string()))?;
-
-        // Try to write with timeout
-        match self.stream.write_all(&encoded) {
-            Ok(_) => match self.stream.flush() {
-                Ok(_) => Ok(()),
-                Err(e) => match e.kind() {
-                    ErrorKind::BrokenPipe
-                    | ErrorKind::ConnectionAborted
-                    | ErrorKind::ConnectionReset => Err(NreplError::ConnectionClosed),
-                    _ => Err(NreplError::IoError(e)),
-                },
-            },
-            Err(e) => match e.kind() {
-                ErrorKind::BrokenPipe
-                | ErrorKind::ConnectionAborted
-                | ErrorKind::ConnectionReset => Err(NreplError::ConnectionClosed),
-                _ => Err(NreplError::IoError(e)),
-            },
+    fn reopen(&mut self, policy: &ReconnectPolicy) -> Result<(), NreplError> {
+        let heartbeat = self.heartbeat.take().map(Heartbeat::stop);
+        let _ = self.stream.shutdown_transport();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
         }
-    }
 
-    fn read_message_with_timeout(
-        &mut self,
-    ) -> Result<HashMap<String, serde_bencode::value::Value>, NreplError> {
-        let mut buffer = Vec::new();
-        let mut temp_buffer = [0u8; 4096];
-        let start_time = Instant::now();
-
-        loop {
-            if start_time.elapsed() > self.read_timeout {
-                return Err(NreplError::Timeout);
+        let mut last_error = NreplError::ConnectionClosed;
+        let mut connection = None;
+        for attempt in 0..policy.max_attempts.max(1) {
+            thread::sleep(policy.delay_before(attempt));
+            match open_connection(&self.endpoint, &self.socket_settings, self.write_timeout) {
+                Ok(opened) => {
+                    connection = Some(opened);
+                    break;
+                }
+                Err(e) => last_error = e,
             }
+        }
+        let Some(connection) = connection else {
+            return Err(last_error);
+        };
+        self.stream = connection.stream;
+        self.writer = connection.writer;
+        self.router = connection.router;
+        self.reader = Some(connection.reader);
+        if let Some((interval, timeout)) = heartbeat {
+            self.start_heartbeat(interval, timeout)?;
+        }
 
-            match self.stream.read(&mut temp_buffer) {
-                Ok(0) => {
-                    // Connection closed
-                    return Err(NreplError::ConnectionClosed);
-                }
-                Ok(n) => {
-                    buffer.extend_from_slice(&temp_buffer[..n]);
-
-                    // Try to decode what we have so far
-                    match serde_bencode::from_bytes::<HashMap<String, serde_bencode::value::Value>>(
-                        &buffer,
-                    ) {
-                        Ok(decoded) => return Ok(decoded),
-                        Err(_) => {
-                            // Need more data, continue reading
-                            // But check if we have too much data (potential attack)
-                            if buffer.len() > 1024 * 1024 {
-                                // 1MB limit
-                                return Err(NreplError::ParseError(
-                                    "Message too large".to_string(),
-                                ));
-                            }
-                            continue;
-                        }
-                    }
+        // Sessions died with the old connection; open replacements
+        self.description = None;
+        self.namespaces.lock().unwrap().clear();
+        let active = self.session.take();
+        let named: Vec<(String, String)> = self.named_sessions.drain().collect();
+        let active_name = named
+            .iter()
+            .find(|(_, id)| active.as_ref() == Some(id))
+            .map(|(name, _)| name.clone());
+
+        let mut sessions = Vec::new();
+        if active.is_some() && active_name.is_none() {
+            sessions.push(self.clone_session()?);
+        }
+        for (name, _) in named {
+            sessions.push(self.new_session(&name)?);
+        }
+        if let Some(name) = active_name {
+            self.switch_session(&name);
+        }
+
+        if let Some(script) = &policy.init_script {
+            for session in sessions {
+                let result = self.eval_in_session(script, &session)?;
+                if result.has_error {
+                    return Err(NreplError::Other(format!(
+                        "Init script failed after reconnect: {}",
+                        result.error.trim()
+                    )));
                 }
-                Err(e) => match e.kind() {
-                    ErrorKind::WouldBlock | ErrorKind::TimedOut => {
-                        if !buffer.is_empty() {
-                            // We have partial data, maybe try to decode it
-                            if let Ok(decoded) = serde_bencode::from_bytes::<
-                                HashMap<String, serde_bencode::value::Value>,
-                            >(&buffer)
-                            {
-                                return Ok(decoded);
-                            }
-                        }
-                        continue;
-                    }
-                    ErrorKind::UnexpectedEof
-                    | ErrorKind::ConnectionAborted
-                    | ErrorKind::ConnectionReset => {
-                        return Err(NreplError::ConnectionClosed);
-                    }
-                    _ => return Err(NreplError::IoError(e)),
-                },
             }
         }
+        Ok(())
+    }
+
+    /// Reconnects if the policy allows, turning a dropped connection into
+    /// `NreplError::Reconnected`. Other errors are passed through.
+    fn recover(&mut self, error: NreplError) -> NreplError {
+        if !matches!(error, NreplError::ConnectionClosed)
+            || self.reconnect_policy.is_none()
+            || self.reconnecting
+        {
+            return error;
+        }
+        match self.reconnect() {
+            Ok(()) => NreplError::Reconnected,
+            Err(e) => e,
+        }
     }
 
-    /// Closes the client connection and ends the session on the nREPL server.
+    /// Sets the read and write timeouts for the client connection.
+    ///
+    /// The read timeout bounds how long single-response operations such as
+    /// `describe` and `clone_session` wait for their reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `read_timeout` - Duration for read timeout.
+    /// * `write_timeout` - Duration for write timeout.
     ///
     /// # Returns
     ///
-    /// Returns `Ok(())` if the connection and session were closed successfully,
-    /// or an `NreplError` if the operation fails.
-    pub fn close(&mut self) -> Result<(), NreplError> {
-        if let Some(session) = &self.session.clone() {
-            let mut msg = HashMap::new();
-            msg.insert(
-                "op".to_string(),
-                serde_bencode::value::Value::Bytes(b"close".to_vec()),
-            );
-            msg.insert(
-                "id".to_string(),
-                serde_bencode::value::Value::Bytes(uuid::Uuid::new_v4().to_string().into_bytes()),
-            );
-            msg.insert(
-                "session".to_string(),
-                serde_bencode::value::Value::Bytes(session.as_bytes().to_vec()),
-            );
-
-            // Best effort - don't fail if close fails
-            let _ = self.send_message(&msg);
-            let _ = self.read_message_with_timeout();
-            self.session = None;
-        }
+    /// Returns `Ok(())` if successful, or an `NreplError` if setting timeouts fails.
+    pub fn set_timeouts(
+        &mut self,
+        read_timeout: Duration,
+        write_timeout: Duration,
+    ) -> Result<(), NreplError> {
+        self.stream
+            .set_transport_write_timeout(Some(write_timeout))?;
+        self.read_timeout = read_timeout;
+        self.write_timeout = write_timeout;
         Ok(())
     }
+
+    /// Sets whether evals that time out are interrupted on the server.
+    ///
+    /// Off by default, in which case a timed-out eval keeps running on the
+    /// server. Applies to `eval`, `eval_with_timeout` and the other methods
+    /// that wait for a result, not to `eval_stream` or `start_eval`.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - `true` to send an `interrupt` for the eval's `id` on timeout.
+    pub fn set_interrupt_on_timeout(&mut self, enabled: bool) {
+        self.interrupt_on_timeout = enabled;
+    }
+
+    /// Creates a new session on the nREPL server and makes it the active session.
+    ///
+    /// The previously active session stays open on the server; close it with
+    /// `close_session` if it is no longer needed.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the new session ID as a `String` if successful,
+    /// or an `NreplError` if the operation fails.
+    pub fn clone_session(&mut self) -> Result<String, NreplError> {
+        let session_id = self.request_clone(None)?;
+        self.session = Some(session_id.clone());
+        Ok(session_id)
+    }
+
+    /// Creates a fresh session and registers it under a name.
+    ///
+    /// The active session is not changed; use `switch_session` or
+    /// `eval_in_session` to evaluate in it.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - A client-side name for the session, unique within this client.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the new session ID, or an `NreplError` if
+    /// the name is already taken or the clone fails.
+    pub fn new_session(&mut self, name: &str) -> Result<String, NreplError> {
+        self.ensure_name_free(name)?;
+        let session_id = self.request_clone(None)?;
+        self.named_sessions
+            .insert(name.to_string(), session_id.clone());
+        Ok(session_id)
+    }
+
+    /// Clones an existing session, so the copy starts with its bindings and
+    /// namespace, and registers the copy under a name.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - Name or ID of the session to copy.
+    /// * `name` - A client-side name for the new session.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the new session ID, or an `NreplError` if
+    /// the name is already taken or the clone fails.
+    pub fn clone_session_from(&mut self, source: &str, name: &str) -> Result<String, NreplError> {
+        self.ensure_name_free(name)?;
+        let source = self.resolve_session(source);
+        let session_id = self.request_clone(Some(&source))?;
+        self.named_sessions
+            .insert(name.to_string(), session_id.clone());
+
+        if let Some(ns) = self.session_ns(&source) {
+            self.namespaces
+                .lock()
+                .unwrap()
+                .insert(session_id.clone(), ns);
+        }
+        Ok(session_id)
+    }
+
+    /// Lists the IDs of every session open on the server, including those of
+    /// other clients, with the `ls-sessions` op.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the session IDs, or an `NreplError` if the
+    /// request fails.
+    pub fn ls_sessions(&mut self) -> Result<Vec<String>, NreplError> {
+        let mut pending = self.send_request(Request::LsSessions.into())?;
+        let responses = self.wait_read(&mut pending)?;
+        Ok(responses
+            .into_iter()
+            .filter_map(|response| Response::from_message(response).sessions)
+            .flatten()
+            .collect())
+    }
+
+    /// Makes the given session the active one used by `eval` and friends.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Name or ID of the session.
+    pub fn switch_session(&mut self, session: &str) {
+        self.session = Some(self.resolve_session(session));
+    }
+
+    /// Returns the ID of the active session, if there is one.
+    pub fn active_session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// Returns the ID of the session registered under a name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name given to `new_session` or `clone_session_from`.
+    pub fn session_id(&self, name: &str) -> Option<&str> {
+        self.named_sessions.get(name).map(String::as_str)
+    }
+
+    /// Closes one session on the server and forgets its name.
+    ///
+    /// If it was the active session, the client has no active session
+    /// afterwards and the next eval creates a new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Name or ID of the session.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the server confirms, or an `NreplError` if the
+    /// request fails.
+    pub fn close_session(&mut self, session: &str) -> Result<(), NreplError> {
+        let session_id = self.resolve_session(session);
+
+        let request = RequestMessage::new(Request::Close).in_session(&session_id);
+        let mut pending = self.send_request(request)?;
+        self.wait_read(&mut pending)?;
+
+        self.forget_session(&session_id);
+        Ok(())
+    }
+
+    /// Sends a `clone` request, copying `source` if given.
+    fn request_clone(&mut self, source: Option<&str>) -> Result<String, NreplError> {
+        let mut request = RequestMessage::new(Request::Clone);
+        request.session = source.map(str::to_string);
+
+        let mut pending = self.send_request(request)?;
+        let response = Response::from_message(self.next_read(&mut pending)?);
+
+        response.new_session.ok_or_else(|| {
+            NreplError::Other("Failed to get session from clone response".to_string())
+        })
+    }
+
+    /// Maps a session name to its ID; anything else is taken to be an ID already.
+    fn resolve_session(&self, session: &str) -> String {
+        self.named_sessions
+            .get(session)
+            .cloned()
+            .unwrap_or_else(|| session.to_string())
+    }
+
+    fn ensure_name_free(&self, name: &str) -> Result<(), NreplError> {
+        if self.named_sessions.contains_key(name) {
+            return Err(NreplError::Other(format!(
+                "Session name '{}' is already in use",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    fn forget_session(&mut self, session_id: &str) {
+        self.named_sessions.retain(|_, id| id != session_id);
+        self.namespaces.lock().unwrap().remove(session_id);
+        if self.session.as_deref() == Some(session_id) {
+            self.session = None;
+        }
+    }
+
+    /// Sets the function that answers `need-input` requests from evaluated code.
+    ///
+    /// Without a provider, `need-input` is answered with end of input so that
+    /// reads return immediately instead of blocking until the eval times out.
+    /// Time spent in the provider counts towards the eval's timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - Called once per `need-input`; returns the text to send,
+    ///   or `None` for end of input.
+    pub fn set_stdin_provider<F>(&mut self, provider: F)
+    where
+        F: FnMut() -> Option<String> + Send + 'static,
+    {
+        *self.stdin_provider.lock().unwrap() = Some(Box::new(provider));
+    }
+
+    /// Sends input to the current session's `*in*` with the `stdin` op.
+    ///
+    /// Evals answer `need-input` on their own; this is for feeding input ahead
+    /// of time. An empty string signals end of input.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The text to send, usually ending in a newline.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the server acknowledges the input,
+    /// or an `NreplError` if the request fails.
+    pub fn send_stdin(&mut self, input: &str) -> Result<(), NreplError> {
+        let request = Request::Stdin {
+            stdin: input.to_string(),
+        };
+        let mut pending = self.send_session_request(request.into())?;
+        self.wait_read(&mut pending)?;
+        Ok(())
+    }
+
+    /// Evaluates the given Clojure code on the nREPL server with a default timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails.
+    pub fn eval(&mut self, code: &str) -> Result<EvalResult, NreplError> {
+        self.eval_with_timeout(code, self.eval_timeout)
+            .map_err(timed_out("eval_timeout", self.eval_timeout))
+    }
+
+    /// Evaluates the given Clojure code and reads its value into a Rust type.
+    ///
+    /// The printed value of the last form is parsed as EDN and deserialized
+    /// as described for [`edn::from_edn`](crate::edn::from_edn).
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the value, or an `NreplError` if the
+    /// evaluation fails, throws, produces no value, or its value doesn't
+    /// parse as a `T`.
+    pub fn eval_as<T: DeserializeOwned>(&mut self, code: &str) -> Result<T, NreplError> {
+        let result = self.eval(code)?;
+        if let Some(exception) = result.exception {
+            return Err(NreplError::Other(format!(
+                "Evaluation threw {}: {}",
+                exception.class,
+                exception.message.trim()
+            )));
+        }
+        let value = result
+            .value()
+            .ok_or_else(|| NreplError::Other("Evaluation produced no value".to_string()))?;
+        edn::from_str(value)
+    }
+
+    /// Evaluates the given Clojure code on the nREPL server with a custom timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for evaluation.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails or times out.
+    pub fn eval_with_timeout(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+    ) -> Result<EvalResult, NreplError> {
+        self.eval_with_callback(code, timeout, |_| {})
+    }
+
+    /// Evaluates the given Clojure code in an explicit namespace.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `ns` - The namespace to evaluate in, such as `user`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails.
+    pub fn eval_in_ns(&mut self, code: &str, ns: &str) -> Result<EvalResult, NreplError> {
+        self.eval_with_options(code, self.eval_timeout, &EvalOptions::new().ns(ns))
+            .map_err(timed_out("eval_timeout", self.eval_timeout))
+    }
+
+    /// Evaluates the given Clojure code in a specific session with a default timeout.
+    ///
+    /// The active session is left unchanged.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `session` - Name or ID of the session to evaluate in.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails or times out.
+    pub fn eval_in_session(&mut self, code: &str, session: &str) -> Result<EvalResult, NreplError> {
+        self.eval_with_options(
+            code,
+            self.eval_timeout,
+            &EvalOptions::new().session(session),
+        )
+        .map_err(timed_out("eval_timeout", self.eval_timeout))
+    }
+
+    /// Evaluates the given Clojure code with additional request options.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for evaluation.
+    /// * `options` - Extra fields to send with the eval request.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails or times out.
+    pub fn eval_with_options(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+        options: &EvalOptions,
+    ) -> Result<EvalResult, NreplError> {
+        let stream = self.eval_stream_with_options(code, timeout, options)?;
+        self.finish_eval(stream, |_| {})
+    }
+
+    /// Evaluates the given Clojure code, reporting each event as it arrives.
+    ///
+    /// Output printed by the evaluated code is passed to `on_event` as soon as
+    /// the server sends it, rather than after the evaluation completes.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for evaluation.
+    /// * `on_event` - Called with every `EvalEvent` in the order received.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the accumulated `EvalResult` if successful,
+    /// or an `NreplError` if the evaluation fails or times out.
+    pub fn eval_with_callback<F>(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+        on_event: F,
+    ) -> Result<EvalResult, NreplError>
+    where
+        F: FnMut(&EvalEvent),
+    {
+        let stream = self.eval_stream(code, timeout)?;
+        self.finish_eval(stream, on_event)
+    }
+
+    /// Evaluates the given Clojure code and returns its events as an iterator.
+    ///
+    /// The iterator yields events as the server sends them and ends after
+    /// `EvalEvent::Done`, or after yielding an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for the whole evaluation.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalStream`,
+    /// or an `NreplError` if the request could not be sent.
+    pub fn eval_stream(&mut self, code: &str, timeout: Duration) -> Result<EvalStream, NreplError> {
+        self.eval_stream_with_options(code, timeout, &EvalOptions::default())
+    }
+
+    /// Evaluates the given Clojure code with request options and returns its events as an iterator.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `timeout` - The maximum duration to wait for the whole evaluation.
+    /// * `options` - Extra fields to send with the eval request.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalStream`,
+    /// or an `NreplError` if the request could not be sent.
+    pub fn eval_stream_with_options(
+        &mut self,
+        code: &str,
+        timeout: Duration,
+        options: &EvalOptions,
+    ) -> Result<EvalStream, NreplError> {
+        let pending = self.start_eval_with_options(code, options)?;
+        let session = match &options.session {
+            Some(session) => Some(self.resolve_session(session)),
+            None => self.session.clone(),
+        };
+        Ok(self.eval_events(pending, timeout, session))
+    }
+
+    /// Loads a Clojure source file into the current session with the `load-file` op.
+    ///
+    /// The file's name and path are sent along with its contents, so definitions
+    /// keep their source location and stack traces point at the real file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the source file to load.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` for the loaded file,
+    /// or an `NreplError` if the file cannot be read or loading fails.
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> Result<EvalResult, NreplError> {
+        self.load_file_with_timeout(path, self.eval_timeout)
+            .map_err(timed_out("eval_timeout", self.eval_timeout))
+    }
+
+    /// Loads a Clojure source file into the current session with a custom timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the source file to load.
+    /// * `timeout` - The maximum duration to wait for loading to finish.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing an `EvalResult` for the loaded file,
+    /// or an `NreplError` if the file cannot be read, loading fails or times out.
+    pub fn load_file_with_timeout<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        timeout: Duration,
+    ) -> Result<EvalResult, NreplError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let request = Request::LoadFile {
+            file: contents,
+            file_name: Some(file_name),
+            file_path: Some(path.to_string_lossy().to_string()),
+        };
+        let pending = self.send_session_request(request.into())?;
+        let stream = self.eval_events(pending, timeout, self.session.clone());
+        self.finish_eval(stream, |_| {})
+    }
+
+    /// Drains an eval stream into an `EvalResult`, interrupting the eval on
+    /// timeout if the client is set up to.
+    fn finish_eval<F>(&mut self, stream: EvalStream, on_event: F) -> Result<EvalResult, NreplError>
+    where
+        F: FnMut(&EvalEvent),
+    {
+        let id = stream.id().to_string();
+        let session = stream.session().map(str::to_string);
+
+        let result = match stream.into_result(on_event) {
+            Err(NreplError::ConnectionClosed) => Err(self.recover(NreplError::ConnectionClosed)),
+            result => result,
+        };
+        if matches!(result, Err(NreplError::Timeout))
+            && self.interrupt_on_timeout
+            && let Some(session) = session
+        {
+            // Best effort - the caller still gets the timeout
+            let _ = self.interrupt_eval(&session, &id);
+        }
+        result
+    }
+
+    /// Wraps an eval-like request in an `EvalStream` that records namespaces
+    /// against the session it runs in.
+    fn eval_events(
+        &self,
+        pending: PendingRequest,
+        timeout: Duration,
+        session: Option<String>,
+    ) -> EvalStream {
+        let tracker = session.clone().map(|session| NsTracker {
+            session,
+            namespaces: Arc::clone(&self.namespaces),
+        });
+        let stdin = session.map(|session| StdinResponder {
+            session,
+            writer: Arc::clone(&self.writer),
+            codec: self.socket_settings.codec,
+            provider: Arc::clone(&self.stdin_provider),
+        });
+        EvalStream::new(pending, timeout, tracker, stdin)
+    }
+
+    /// Sends the given Clojure code for evaluation without waiting for the result.
+    ///
+    /// A session is created first if the client does not have one yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a `PendingRequest` that receives the
+    /// evaluation's responses, or an `NreplError` if sending fails.
+    pub fn start_eval(&mut self, code: &str) -> Result<PendingRequest, NreplError> {
+        self.start_eval_with_options(code, &EvalOptions::default())
+    }
+
+    /// Sends the given Clojure code for evaluation with request options, without waiting.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Clojure code to evaluate.
+    /// * `options` - Extra fields to send with the eval request.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a `PendingRequest` that receives the
+    /// evaluation's responses, or an `NreplError` if sending fails.
+    pub fn start_eval_with_options(
+        &mut self,
+        code: &str,
+        options: &EvalOptions,
+    ) -> Result<PendingRequest, NreplError> {
+        let request = options.to_request(code);
+        if let Some(session) = &options.session {
+            let session = self.resolve_session(session);
+            return self.send_request(request.in_session(&session));
+        }
+
+        self.send_session_request(request)
+    }
+
+    /// Sends a request in the current session, creating a session first if needed.
+    pub(crate) fn send_session_request(
+        &mut self,
+        mut request: RequestMessage,
+    ) -> Result<PendingRequest, NreplError> {
+        // Ensure there a session already otherwise create new
+        if self.session.is_none() {
+            self.clone_session()?;
+        }
+
+        request.session = self.session.clone();
+        self.send_request(request)
+    }
+
+    /// Returns the namespace the current session was last seen evaluating in.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Some(ns)` once an evaluation in the current session has reported
+    /// its namespace, or `None` otherwise.
+    pub fn current_ns(&self) -> Option<String> {
+        self.session
+            .as_ref()
+            .and_then(|session| self.session_ns(session))
+    }
+
+    /// Returns the namespace the given session was last seen evaluating in.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The session ID.
+    pub fn session_ns(&self, session: &str) -> Option<String> {
+        self.namespaces.lock().unwrap().get(session).cloned()
+    }
+
+    /// Fetches the stack trace of the exception recorded in an `EvalResult`.
+    ///
+    /// Uses the `stacktrace` op when the server's middleware provides it, and
+    /// otherwise evaluates `*e`, in the session the result came from. Must be
+    /// called before that session evaluates anything else that throws.
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - The result of a failed evaluation.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once `result.exception.stacktrace` is filled in (or if the
+    /// result has no exception), or an `NreplError` if the request fails.
+    pub fn fetch_stacktrace(&mut self, result: &mut EvalResult) -> Result<(), NreplError> {
+        let Some(exception) = result.exception.as_mut() else {
+            return Ok(());
+        };
+
+        let session = result.session.clone().or_else(|| self.session.clone());
+        let frames = if self.server_supports("stacktrace")? {
+            self.stacktrace_via_op(session)?
+        } else {
+            self.stacktrace_via_eval(session)?
+        };
+        exception.stacktrace = Some(frames);
+        Ok(())
+    }
+
+    fn stacktrace_via_op(
+        &mut self,
+        session: Option<String>,
+    ) -> Result<Vec<StackFrame>, NreplError> {
+        let mut request = RequestMessage::new(Request::Stacktrace);
+        request.session = session;
+
+        let mut pending = self.send_request(request)?;
+        let responses = self.wait_read(&mut pending)?;
+
+        // One response is sent per cause, outermost first; use the thrown exception's frames.
+        let frames = responses
+            .iter()
+            .find_map(|response| match response.get("stacktrace") {
+                Some(Value::List(frames)) => Some(frames),
+                _ => None,
+            })
+            .map(|frames| {
+                frames
+                    .iter()
+                    .filter_map(|frame| match frame {
+                        Value::Dict(fields) => Some(frame_from_dict(fields)),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(frames)
+    }
+
+    fn stacktrace_via_eval(
+        &mut self,
+        session: Option<String>,
+    ) -> Result<Vec<StackFrame>, NreplError> {
+        let options = EvalOptions {
+            session,
+            ..EvalOptions::default()
+        };
+        let result = self
+            .eval_with_options(STACKTRACE_EVAL, self.read_timeout, &options)
+            .map_err(timed_out("read_timeout", self.read_timeout))?;
+        Ok(result
+            .output
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(StackFrame::parse)
+            .collect())
+    }
+
+    /// Checks whether the server handles the given op.
+    ///
+    /// The first call sends a `describe` request; later calls reuse its reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The op name, such as `"complete"`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the server lists the op, or an `NreplError` if the
+    /// `describe` request fails.
+    pub fn server_supports(&mut self, op: &str) -> Result<bool, NreplError> {
+        if self.description.is_none() {
+            self.describe()?;
+        }
+        Ok(self
+            .description
+            .as_ref()
+            .is_some_and(|description| description.supports_op(op)))
+    }
+
+    /// Requests a description of the nREPL server's capabilities and operations.
+    ///
+    /// The reply is remembered and used by `server_supports`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the `ServerDescription` if successful,
+    /// or an `NreplError` if the operation fails.
+    pub fn describe(&mut self) -> Result<ServerDescription, NreplError> {
+        let mut pending = self.start_describe()?;
+        let response = self.next_read(&mut pending)?;
+        let description = ServerDescription::from_response(&response);
+        self.description = Some(description.clone());
+        Ok(description)
+    }
+
+    /// Sends a `describe` request without waiting for the reply.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a `PendingRequest` that receives the
+    /// server description, or an `NreplError` if sending fails.
+    pub fn start_describe(&mut self) -> Result<PendingRequest, NreplError> {
+        self.send_request(Request::Describe.into())
+    }
+
+    /// Completes a symbol prefix using the server's `complete` op.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The text typed so far, such as `"str/jo"`.
+    /// * `ns` - Namespace to resolve the prefix in; defaults to the session's
+    ///   current namespace.
+    /// * `context` - The surrounding form with the prefix replaced by
+    ///   `__prefix__`, for servers that use it to narrow candidates.
+    ///
+    /// # Returns
+    ///
+    /// Returns the candidates in the order the server sent them, or
+    /// `NreplError::UnsupportedOp` if the server does not handle `complete`.
+    pub fn complete(
+        &mut self,
+        prefix: &str,
+        ns: Option<&str>,
+        context: Option<&str>,
+    ) -> Result<Vec<Completion>, NreplError> {
+        if !self.server_supports("complete")? {
+            return Err(NreplError::UnsupportedOp("complete".to_string()));
+        }
+
+        let mut request = RequestMessage::new(Request::Complete {
+            prefix: prefix.to_string(),
+            ns: ns.map(str::to_string),
+            context: context.map(str::to_string),
+        });
+        request.session = self.session.clone();
+
+        let mut pending = self.send_request(request)?;
+        let responses = self.wait_read(&mut pending)?;
+        Ok(completions_from_responses(&responses))
+    }
+
+    /// Looks up a symbol's metadata using the server's `lookup` op.
+    ///
+    /// # Arguments
+    ///
+    /// * `sym` - The symbol to look up, optionally namespace-qualified.
+    /// * `ns` - Namespace to resolve the symbol in; defaults to the session's
+    ///   current namespace.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(None)` if the symbol cannot be resolved, or
+    /// `NreplError::UnsupportedOp` if the server does not handle `lookup`.
+    pub fn lookup(
+        &mut self,
+        sym: &str,
+        ns: Option<&str>,
+    ) -> Result<Option<SymbolInfo>, NreplError> {
+        if !self.server_supports("lookup")? {
+            return Err(NreplError::UnsupportedOp("lookup".to_string()));
+        }
+
+        let mut request = RequestMessage::new(Request::Lookup {
+            sym: sym.to_string(),
+            ns: ns.map(str::to_string),
+        });
+        request.session = self.session.clone();
+
+        let mut pending = self.send_request(request)?;
+        let responses = self.wait_read(&mut pending)?;
+        Ok(SymbolInfo::from_responses(&responses))
+    }
+
+    /// Sends any op, such as one provided by middleware, and collects its replies.
+    ///
+    /// The request is sent in the current session if there is one, unless
+    /// `params` names a session itself. Parameters may be any bencode value,
+    /// including nested lists and dicts; an `id` in `params` is replaced by
+    /// the client's own.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The op name, such as `"info"` or `"ns-list"`.
+    /// * `params` - The op's other fields.
+    ///
+    /// # Returns
+    ///
+    /// Returns every response to the request, ending with the `done` one,
+    /// `NreplError::UnsupportedOp` if the server does not handle `op`, or an
+    /// `NreplError` if the request fails or times out.
+    pub fn request(&mut self, op: &str, params: Message) -> Result<Vec<Response>, NreplError> {
+        let mut pending = self.start_request(op, params)?;
+        let responses: Vec<Response> = self
+            .wait_read(&mut pending)?
+            .into_iter()
+            .map(Response::from_message)
+            .collect();
+        if responses.iter().any(|r| r.has_status(&Status::UnknownOp)) {
+            return Err(NreplError::UnsupportedOp(op.to_string()));
+        }
+        Ok(responses)
+    }
+
+    /// Sends any op without waiting for its replies.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The op name.
+    /// * `params` - The op's other fields, as for `request`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing a `PendingRequest` that receives the
+    /// op's responses, or an `NreplError` if sending fails.
+    pub fn start_request(
+        &mut self,
+        op: &str,
+        mut params: Message,
+    ) -> Result<PendingRequest, NreplError> {
+        params.insert("op".to_string(), Value::Bytes(op.as_bytes().to_vec()));
+        let mut request = RequestMessage::from_message(params)?;
+        request.session = match request.session.take() {
+            Some(session) => Some(self.resolve_session(&session)),
+            None => self.session.clone(),
+        };
+        self.send_request(request)
+    }
+
+    /// Interrupts whatever is running in the current session.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing how the server answered, or an
+    /// `NreplError` if the request fails. Without a session there is nothing
+    /// to interrupt and `InterruptOutcome::SessionIdle` is returned.
+    pub fn interrupt(&mut self) -> Result<InterruptOutcome, NreplError> {
+        match self.session.clone() {
+            Some(session) => self.send_interrupt(&session, None),
+            None => Ok(InterruptOutcome::SessionIdle),
+        }
+    }
+
+    /// Interrupts one evaluation, identified by the `id` of its eval request.
+    ///
+    /// The server only interrupts the session's running eval if its `id`
+    /// matches, so a stale interrupt cannot stop a later evaluation.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Name or ID of the session the eval runs in, as returned
+    ///   by `PendingRequest::session` or `EvalStream::session`.
+    /// * `interrupt_id` - The eval request's `id`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing how the server answered, or an
+    /// `NreplError` if the request fails.
+    pub fn interrupt_eval(
+        &mut self,
+        session: &str,
+        interrupt_id: &str,
+    ) -> Result<InterruptOutcome, NreplError> {
+        let session = self.resolve_session(session);
+        self.send_interrupt(&session, Some(interrupt_id))
+    }
+
+    fn send_interrupt(
+        &mut self,
+        session: &str,
+        interrupt_id: Option<&str>,
+    ) -> Result<InterruptOutcome, NreplError> {
+        let request = Request::Interrupt {
+            interrupt_id: interrupt_id.map(str::to_string),
+        };
+
+        // The reply is routed by this request's own id, so responses from the
+        // eval being interrupted cannot be mistaken for it.
+        let mut pending = self.send_request(RequestMessage::new(request).in_session(session))?;
+        let responses: Vec<Response> = self
+            .wait_read(&mut pending)?
+            .into_iter()
+            .map(Response::from_message)
+            .collect();
+        let any_status = |status: Status| responses.iter().any(|r| r.has_status(&status));
+
+        if any_status(Status::SessionIdle) {
+            Ok(InterruptOutcome::SessionIdle)
+        } else if any_status(Status::InterruptIdMismatch) {
+            Ok(InterruptOutcome::IdMismatch)
+        } else if any_status(Status::Error) || any_status(Status::UnknownOp) {
+            Err(NreplError::Other("Interrupt failed".to_string()))
+        } else {
+            Ok(InterruptOutcome::Interrupted)
+        }
+    }
+
+    /// Checks if the client is still connected to the nREPL server.
+    ///
+    /// This sends nothing: it reports whether the reader thread has seen the
+    /// connection close. A connection that died silently is only noticed once
+    /// a request fails, TCP keepalive gives up, or the heartbeat (see
+    /// `start_heartbeat`) times out. Use `ping` for an explicit round trip.
+    ///
+    /// # Returns
+    ///
+    /// Returns `true` if the connection is alive, `false` otherwise.
+    pub fn is_connected(&self) -> bool {
+        !self.router.is_closed()
+    }
+
+    /// Sends a `describe` request and waits for the reply.
+    ///
+    /// The reply is routed by its own `id`, so responses to evals in flight
+    /// are not disturbed, and a busy server still answers.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the round-trip time, or an `NreplError`
+    /// if the server does not answer within the read timeout.
+    pub fn ping(&mut self) -> Result<Duration, NreplError> {
+        let started = Instant::now();
+        let mut pending = self.start_describe()?;
+        self.wait_read(&mut pending)?;
+        Ok(started.elapsed())
+    }
+
+    /// Starts a background thread that pings the server at a fixed interval.
+    ///
+    /// If a ping is not answered within `timeout`, the connection is treated
+    /// as dead: it is shut down, pending requests fail with
+    /// `NreplError::ConnectionClosed` and `is_connected` returns `false`.
+    /// Replaces any heartbeat already running. The heartbeat carries over
+    /// to new connections made by `reconnect`.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - Time between pings.
+    /// * `timeout` - How long to wait for each ping's reply.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the thread is running, or an `NreplError` if it
+    /// cannot be started.
+    pub fn start_heartbeat(
+        &mut self,
+        interval: Duration,
+        timeout: Duration,
+    ) -> Result<(), NreplError> {
+        self.stop_heartbeat();
+        self.heartbeat = Some(Heartbeat::start(
+            Arc::clone(&self.writer),
+            self.socket_settings.codec,
+            Arc::clone(&self.router),
+            self.stream.try_clone_transport()?,
+            interval,
+            timeout,
+        )?);
+        Ok(())
+    }
+
+    /// Stops the background heartbeat, if one is running.
+    pub fn stop_heartbeat(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.take() {
+            heartbeat.stop();
+        }
+    }
+
+    /// Assigns a fresh `id` to the message, registers it with the router and sends it.
+    ///
+    /// The route is registered before the message is written so that replies
+    /// arriving immediately are not lost.
+    fn send_request(&mut self, mut request: RequestMessage) -> Result<PendingRequest, NreplError> {
+        let id = uuid::Uuid::new_v4().to_string();
+        request.id = Some(id.clone());
+
+        let (route, receiver) = match Route::register(&self.router, id) {
+            Ok(registered) => registered,
+            Err(e) => return Err(self.recover(e)),
+        };
+        let pending = PendingRequest {
+            route,
+            session: request.session.clone(),
+            receiver,
+        };
+        if let Err(e) = self.send_message(&request.to_message()) {
+            drop(pending);
+            return Err(self.recover(e));
+        }
+        Ok(pending)
+    }
+
+    fn send_message(&mut self, msg: &Message) -> Result<(), NreplError> {
+        write_message(&self.writer, self.socket_settings.codec, msg).map_err(|e| match e {
+            NreplError::IoError(e)
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                NreplError::LimitExceeded {
+                    setting: "write_timeout",
+                    limit: format!("{:?}", self.write_timeout),
+                }
+            }
+            e => e,
+        })
+    }
+
+    /// Collects every response to a request within the read timeout.
+    fn wait_read(&self, pending: &mut PendingRequest) -> Result<Vec<Message>, NreplError> {
+        pending
+            .wait(self.read_timeout)
+            .map_err(timed_out("read_timeout", self.read_timeout))
+    }
+
+    /// Waits for the next response to a request within the read timeout.
+    fn next_read(&self, pending: &mut PendingRequest) -> Result<Message, NreplError> {
+        pending
+            .next_response(self.read_timeout)
+            .map_err(timed_out("read_timeout", self.read_timeout))
+    }
+
+    /// Closes the client connection and ends its sessions on the nREPL server.
+    ///
+    /// Both the active session and every named session are closed.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` if the connection and sessions were closed successfully,
+    /// or an `NreplError` if the operation fails.
+    pub fn close(&mut self) -> Result<(), NreplError> {
+        for session in self.take_sessions() {
+            let request = RequestMessage::new(Request::Close).in_session(&session);
+
+            // Best effort - don't fail if close fails
+            if !self.router.is_closed()
+                && let Ok(mut pending) = self.send_request(request)
+            {
+                let _ = pending.next_response(self.read_timeout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the active and named sessions, forgetting them.
+    fn take_sessions(&mut self) -> Vec<String> {
+        let mut sessions: Vec<String> = self.named_sessions.drain().map(|(_, id)| id).collect();
+        if let Some(session) = self.session.take()
+            && !sessions.contains(&session)
+        {
+            sessions.push(session);
+        }
+        sessions
+    }
 }
 
 impl Drop for NreplClient {
     fn drop(&mut self) {
-        let _ = self.close();
+        self.stop_heartbeat();
+        // Unlike `close`, don't wait for replies: the server may have stopped
+        // answering, and a failed write must not trigger a reconnect.
+        for session in self.take_sessions() {
+            if self.router.is_closed() {
+                break;
+            }
+            let mut request = RequestMessage::new(Request::Close).in_session(&session);
+            request.id = Some(uuid::Uuid::new_v4().to_string());
+            if self.send_message(&request.to_message()).is_err() {
+                break;
+            }
+        }
+        // Unblock the reader thread and wait for it to finish
+        let _ = self.stream.shutdown_transport();
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
     }
 }
 
 #[test]
+#[ignore = "requires the Clojure CLI (clj) on PATH"]
 fn test_robust_connection() {
     use crate::server::*;
     let mut server = NreplServer::new();
@@ -500,7 +1866,7 @@ fn test_robust_connection() {
 
         // Test basic eval
         let result = client.eval("(+ 1 1)").unwrap();
-        assert_eq!(result.value, Some("2".to_string()));
+        assert_eq!(result.value(), Some("2"));
 
         // Test timeout handling
         let result = client.eval_with_timeout("(Thread/sleep 100)", Duration::from_millis(50));
@@ -508,10 +1874,1179 @@ fn test_robust_connection() {
 
         // test clojure std lib
         let result = client.eval("(require '[clojure.string :as str])").unwrap();
-        assert_eq!(result.value, Some("nil".to_string()));
+        assert_eq!(result.value(), Some("nil"));
         let result = client.eval("(str/reverse \"abc\")").unwrap();
-        assert_eq!(result.value, Some("\"cba\"".to_string()));
+        assert_eq!(result.value(), Some("\"cba\""));
         let _ = client.close();
         let _ = server.stop();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+
+    fn connect(server: &MockServer) -> NreplClient {
+        NreplClient::connect("127.0.0.1", server.port()).unwrap()
+    }
+
+    #[test]
+    fn test_drop_does_not_wait_for_close_replies() {
+        let server = MockServer::start().unwrap();
+        // A server that never answers `close`
+        server.on_op("close", Reply::new());
+        let mut client = connect(&server);
+        client.new_session("a").unwrap();
+        client.new_session("b").unwrap();
+        client.clone_session().unwrap();
+
+        let started = Instant::now();
+        drop(client);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let closes = || {
+            server
+                .received()
+                .iter()
+                .filter(|message| string_field(message, "op").as_deref() == Some("close"))
+                .count()
+        };
+        while closes() < 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(closes(), 3);
+    }
+
+    #[test]
+    fn test_robust_connection_against_mock() {
+        let server = MockServer::start_standard().unwrap();
+        let mut client = connect(&server);
+
+        // Test multiple operations
+        assert!(client.describe().is_ok());
+        assert!(client.clone_session().is_ok());
+
+        // Test basic eval
+        let result = client.eval("(+ 1 1)").unwrap();
+        assert_eq!(result.value(), Some("2"));
+
+        // Test timeout handling
+        let result = client.eval_with_timeout("(Thread/sleep 100)", Duration::from_millis(50));
+        assert!(matches!(result, Err(NreplError::Timeout)));
+
+        // test clojure std lib
+        let result = client.eval("(require '[clojure.string :as str])").unwrap();
+        assert_eq!(result.value(), Some("nil"));
+        let result = client.eval("(str/reverse \"abc\")").unwrap();
+        assert_eq!(result.value(), Some("\"cba\""));
+
+        let session = client.session.clone().unwrap();
+        assert!(server.sessions().contains(&session));
+        client.close().unwrap();
+        assert!(!server.sessions().contains(&session));
+    }
+
+    #[test]
+    fn test_describe_returns_typed_description() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+
+        let description = client.describe().unwrap();
+        assert!(description.supports_op("load-file"));
+        assert!(!description.supports_op("complete"));
+        assert_eq!(
+            description
+                .versions
+                .nrepl
+                .as_ref()
+                .map(|v| v.version_string.as_str()),
+            Some("1.3.1")
+        );
+        assert_eq!(
+            description.aux_string("current-ns"),
+            Some("user".to_string())
+        );
+
+        // Capability checks reuse the cached reply
+        assert!(client.server_supports("eval").unwrap());
+        assert!(!client.server_supports("complete").unwrap());
+        let describes = server
+            .received()
+            .iter()
+            .filter(|message| string_field(message, "op").as_deref() == Some("describe"))
+            .count();
+        assert_eq!(describes, 1);
+    }
+
+    #[test]
+    fn test_complete_returns_typed_candidates() {
+        let server = MockServer::start().unwrap();
+        server.on_op(
+            "describe",
+            Reply::new()
+                .message(&[(
+                    "ops",
+                    Value::Dict(HashMap::from([(
+                        b"complete".to_vec(),
+                        Value::Dict(HashMap::new()),
+                    )])),
+                )])
+                .done(),
+        );
+        let join = Value::Dict(HashMap::from([
+            (b"candidate".to_vec(), Value::Bytes(b"str/join".to_vec())),
+            (b"type".to_vec(), Value::Bytes(b"function".to_vec())),
+            (b"ns".to_vec(), Value::Bytes(b"clojure.string".to_vec())),
+        ]));
+        server.on_op(
+            "complete",
+            Reply::new()
+                .message(&[("completions", Value::List(vec![join]))])
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let completions = client
+            .complete("str/jo", Some("user"), Some("(__prefix__ \",\" xs)"))
+            .unwrap();
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].candidate, "str/join");
+        assert_eq!(completions[0].kind, CompletionKind::Function);
+        assert_eq!(completions[0].ns.as_deref(), Some("clojure.string"));
+
+        let request = server.received_op("complete").unwrap();
+        assert_eq!(string_field(&request, "prefix").as_deref(), Some("str/jo"));
+        assert_eq!(string_field(&request, "ns").as_deref(), Some("user"));
+        assert!(request.contains_key("context"));
+    }
+
+    #[test]
+    fn test_complete_unsupported_op() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+
+        let result = client.complete("pri", None, None);
+        assert!(matches!(result, Err(NreplError::UnsupportedOp(op)) if op == "complete"));
+    }
+
+    #[test]
+    fn test_lookup_returns_symbol_info() {
+        let server = MockServer::start().unwrap();
+        let info = Value::Dict(HashMap::from([
+            (b"name".to_vec(), Value::Bytes(b"inc".to_vec())),
+            (b"ns".to_vec(), Value::Bytes(b"clojure.core".to_vec())),
+            (b"arglists".to_vec(), Value::Bytes(b"([x])".to_vec())),
+            (b"line".to_vec(), Value::Int(924)),
+        ]));
+        server.on_op("lookup", Reply::new().message(&[("info", info)]).done());
+        let mut client = connect(&server);
+
+        let symbol = client.lookup("inc", Some("user")).unwrap().unwrap();
+        assert_eq!(symbol.name, "inc");
+        assert_eq!(symbol.ns.as_deref(), Some("clojure.core"));
+        assert_eq!(symbol.arglists, vec!["[x]"]);
+        assert_eq!(symbol.line, Some(924));
+
+        let request = server.received_op("lookup").unwrap();
+        assert_eq!(string_field(&request, "sym").as_deref(), Some("inc"));
+
+        // The mock's built-in lookup knows no symbols
+        let other = MockServer::start().unwrap();
+        let mut client = connect(&other);
+        assert_eq!(client.lookup("nope", None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_request_sends_arbitrary_op_with_nested_params() {
+        for codec in [Codec::Bencode, Codec::Edn] {
+            let server = MockServer::start_with_codec(codec).unwrap();
+            let var = Value::Dict(HashMap::from([
+                (b"name".to_vec(), Value::Bytes(b"foo-test".to_vec())),
+                (b"line".to_vec(), Value::Int(12)),
+            ]));
+            server.on_op(
+                "test-var-query",
+                Reply::new()
+                    .message(&[("results", Value::List(vec![var.clone()]))])
+                    .message(&[("summary", Value::Dict(HashMap::new()))])
+                    .done(),
+            );
+            let mut client = NreplClient::builder()
+                .codec(codec)
+                .connect("127.0.0.1", server.port())
+                .unwrap();
+            client.new_session("tests").unwrap();
+            let session_id = client.session_id("tests").unwrap().to_string();
+
+            let query = Value::Dict(HashMap::from([(
+                b"ns-query".to_vec(),
+                Value::Dict(HashMap::from([(
+                    b"exactly".to_vec(),
+                    Value::List(vec![Value::Bytes(b"app.core-test".to_vec())]),
+                )])),
+            )]));
+            let params = Message::from([
+                ("var-query".to_string(), query.clone()),
+                ("fail-fast".to_string(), Value::Int(1)),
+                ("session".to_string(), Value::Bytes(b"tests".to_vec())),
+            ]);
+            let responses = client.request("test-var-query", params).unwrap();
+            assert_eq!(responses.len(), 3);
+            assert_eq!(responses[0].extras["results"], Value::List(vec![var]));
+            assert!(responses[2].is_done());
+            assert!(
+                responses
+                    .iter()
+                    .all(|r| r.session.as_ref() == Some(&session_id))
+            );
+
+            let sent = server.received_op("test-var-query").unwrap();
+            assert_eq!(sent["var-query"], query);
+            assert_eq!(sent["fail-fast"], Value::Int(1));
+            assert_eq!(string_field(&sent, "session"), Some(session_id));
+        }
+    }
+
+    #[test]
+    fn test_request_unknown_op() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+
+        let result = client.request("format-code", Message::new());
+        assert!(matches!(result, Err(NreplError::UnsupportedOp(op)) if op == "format-code"));
+    }
+
+    #[test]
+    fn test_need_input_is_answered_by_provider() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(read-line)", Reply::new().read_input().done());
+        let mut client = connect(&server);
+
+        let asked = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&asked);
+        client.set_stdin_provider(move || {
+            *counter.lock().unwrap() += 1;
+            Some("hello\n".to_string())
+        });
+
+        let result = client
+            .eval_with_timeout("(read-line)", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(result.value(), Some("\"hello\""));
+        assert_eq!(*asked.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_need_input_without_provider_sends_end_of_input() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(read-line)", Reply::new().read_input().done());
+        let mut client = connect(&server);
+
+        let result = client
+            .eval_with_timeout("(read-line)", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(result.value(), Some("nil"));
+    }
+
+    #[test]
+    fn test_send_stdin_ahead_of_read() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(read-line)", Reply::new().read_input().done());
+        let mut client = connect(&server);
+        client.clone_session().unwrap();
+        client.set_stdin_provider(|| panic!("input was already sent"));
+
+        client.send_stdin("early\n").unwrap();
+        let result = client
+            .eval_with_timeout("(read-line)", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(result.value(), Some("\"early\""));
+    }
+
+    #[test]
+    fn test_named_sessions_are_isolated() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+
+        let alice = client.new_session("alice").unwrap();
+        let bob = client.new_session("bob").unwrap();
+        assert_ne!(alice, bob);
+        assert_eq!(client.session_id("alice"), Some(alice.as_str()));
+        assert_eq!(client.active_session(), None);
+        assert!(matches!(
+            client.new_session("alice"),
+            Err(NreplError::Other(_))
+        ));
+
+        client.eval_in_session("(+ 1 1)", "alice").unwrap();
+        client.eval_in_session("(+ 2 2)", &bob).unwrap();
+        let sessions_used: Vec<Option<String>> = server
+            .received()
+            .iter()
+            .filter(|message| string_field(message, "op").as_deref() == Some("eval"))
+            .map(|message| string_field(message, "session"))
+            .collect();
+        assert_eq!(sessions_used, vec![Some(alice.clone()), Some(bob.clone())]);
+        assert_eq!(client.active_session(), None);
+
+        client.switch_session("bob");
+        assert_eq!(client.active_session(), Some(bob.as_str()));
+
+        let listed = client.ls_sessions().unwrap();
+        assert!(listed.contains(&alice) && listed.contains(&bob));
+
+        client.close_session("bob").unwrap();
+        assert_eq!(client.active_session(), None);
+        assert_eq!(client.session_id("bob"), None);
+        assert!(!server.sessions().contains(&bob));
+        assert!(server.sessions().contains(&alice));
+
+        client.close().unwrap();
+        assert!(server.sessions().is_empty());
+    }
+
+    #[test]
+    fn test_clone_session_from_copies_source() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(in-ns 'app)", Reply::new().value("nil").done());
+        let mut client = connect(&server);
+
+        let base = client.new_session("base").unwrap();
+        client.eval_in_session("(in-ns 'app)", "base").unwrap();
+
+        let copy = client.clone_session_from("base", "copy").unwrap();
+        let request = server.received_op("clone").unwrap();
+        assert_eq!(string_field(&request, "session"), Some(base.clone()));
+        assert_eq!(client.session_ns(&copy), client.session_ns(&base));
+    }
+
+    #[test]
+    fn test_reconnect_recreates_session_and_replays_init_script() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+        client.set_reconnect_policy(Some(
+            ReconnectPolicy::new()
+                .backoff(Duration::from_millis(10), Duration::from_millis(50))
+                .init_script("(require '[clojure.string :as str])"),
+        ));
+        let old_session = client.clone_session().unwrap();
+        client.eval("(+ 1 1)").unwrap();
+
+        server.disconnect_all();
+        let result = client.eval("(+ 1 1)");
+        assert!(matches!(result, Err(NreplError::Reconnected)));
+
+        let new_session = client.active_session().unwrap().to_string();
+        assert_ne!(new_session, old_session);
+        let init = server
+            .received()
+            .into_iter()
+            .find(|message| {
+                string_field(message, "code").as_deref()
+                    == Some("(require '[clojure.string :as str])")
+            })
+            .unwrap();
+        assert_eq!(string_field(&init, "session"), Some(new_session));
+
+        let result = client.eval("(+ 1 1)").unwrap();
+        assert_eq!(result.value(), Some("nil"));
+    }
+
+    #[test]
+    fn test_reconnect_restores_the_active_named_session() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+        client.set_reconnect_policy(Some(
+            ReconnectPolicy::new()
+                .backoff(Duration::from_millis(10), Duration::from_millis(50))
+                .init_script("(init)"),
+        ));
+        client.new_session("work").unwrap();
+        client.new_session("other").unwrap();
+        client.switch_session("work");
+
+        server.disconnect_all();
+        let result = client.eval("(+ 1 1)");
+        assert!(matches!(result, Err(NreplError::Reconnected)));
+
+        assert_eq!(client.active_session(), client.session_id("work"));
+        assert_ne!(client.session_id("work"), client.session_id("other"));
+        // Two sessions from before the drop and one replacement for each
+        assert_eq!(server.sessions().len(), 4);
+        let inits = server
+            .received()
+            .iter()
+            .filter(|message| string_field(message, "code").as_deref() == Some("(init)"))
+            .count();
+        assert_eq!(inits, 2);
+    }
+
+    #[test]
+    fn test_reconnect_gives_up_after_max_attempts() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+        client.set_reconnect_policy(Some(
+            ReconnectPolicy::new()
+                .max_attempts(2)
+                .backoff(Duration::from_millis(10), Duration::from_millis(10)),
+        ));
+        client.clone_session().unwrap();
+
+        drop(server);
+        let result = client.eval("(+ 1 1)");
+        assert!(matches!(result, Err(NreplError::IoError(_))));
+    }
+
+    #[test]
+    fn test_liveness_check_does_not_disturb_evals() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(slow)",
+            Reply::new()
+                .delay(Duration::from_millis(200))
+                .value("42")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let stream = client
+            .eval_stream("(slow)", Duration::from_secs(5))
+            .unwrap();
+        assert!(client.is_connected());
+        client.ping().unwrap();
+
+        let result = stream.into_result(|_| {}).unwrap();
+        assert_eq!(result.value(), Some("42"));
+    }
+
+    #[test]
+    fn test_heartbeat_detects_unresponsive_server() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+        client
+            .start_heartbeat(Duration::from_millis(20), Duration::from_millis(200))
+            .unwrap();
+
+        // Replies keep coming while the server answers
+        thread::sleep(Duration::from_millis(100));
+        assert!(client.is_connected());
+
+        // A server that stops answering is declared dead without a request
+        server.on_op("describe", Reply::new());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while client.is_connected() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_builder_applies_socket_settings() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(slow)", Reply::new().delay(Duration::from_secs(5)).done());
+        let builder = NreplClient::builder()
+            .connect_timeout(Duration::from_secs(1))
+            .keepalive_idle(Duration::from_secs(30))
+            .keepalive_interval(Duration::from_secs(5))
+            .keepalive_count(3)
+            .nodelay(true)
+            .eval_timeout(Duration::from_millis(50));
+
+        let stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+        configure_tcp(&stream, &builder.socket).unwrap();
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+            assert_eq!(socket.keepalive_retries().unwrap(), 3);
+        }
+
+        let mut client = builder.connect("127.0.0.1", server.port()).unwrap();
+        assert!(matches!(
+            client.eval("(slow)"),
+            Err(NreplError::LimitExceeded {
+                setting: "eval_timeout",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_timeouts_name_their_setting() {
+        let server = MockServer::start().unwrap();
+        server.on_op("describe", Reply::new());
+        let mut client = NreplClient::builder()
+            .read_timeout(Duration::from_millis(50))
+            .connect("127.0.0.1", server.port())
+            .unwrap();
+        let err = client.describe().unwrap_err();
+        assert!(matches!(
+            err,
+            NreplError::LimitExceeded {
+                setting: "read_timeout",
+                ..
+            }
+        ));
+        assert_eq!(err.to_string(), "read_timeout exceeded (limit 50ms)");
+
+        // A server that never reads lets the socket buffers fill up
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut client = NreplClient::builder()
+            .write_timeout(Duration::from_millis(100))
+            .connect("127.0.0.1", port)
+            .unwrap();
+        let _server_end = listener.accept().unwrap();
+        client.switch_session("stalled");
+        let code = "x".repeat(64 * 1024 * 1024);
+        assert!(matches!(
+            client.eval(&code),
+            Err(NreplError::LimitExceeded {
+                setting: "write_timeout",
+                ..
+            })
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_connect_over_unix_socket() {
+        let path = std::env::temp_dir().join(format!("nrepl-{}.sock", uuid::Uuid::new_v4()));
+        let server = MockServer::start_unix(&path).unwrap();
+        server.on_eval("(+ 1 2)", Reply::new().value("3").done());
+
+        let mut client = NreplClient::connect_unix(&path).unwrap();
+        assert_eq!(client.eval("(+ 1 2)").unwrap().value(), Some("3"));
+        assert!(client.describe().unwrap().supports_op("eval"));
+
+        drop(client);
+        drop(server);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_edn_codec_end_to_end() {
+        let server = MockServer::start_with_codec(Codec::Edn).unwrap();
+        server.on_eval(
+            "(str \"a\" \\b)",
+            Reply::new().out("{:x 1}\n").value("\"ab\"").done(),
+        );
+
+        let mut client = NreplClient::builder()
+            .codec(Codec::Edn)
+            .connect("127.0.0.1", server.port())
+            .unwrap();
+        let result = client.eval("(str \"a\" \\b)").unwrap();
+        assert_eq!(result.value(), Some("\"ab\""));
+        assert_eq!(result.output, "{:x 1}\n");
+        assert!(client.describe().unwrap().supports_op("eval"));
+    }
+
+    #[test]
+    fn test_oversized_response_names_max_message_size() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(apply str (repeat 200 \"x\"))",
+            Reply::new().value(&"x".repeat(200)).done(),
+        );
+        let mut client = NreplClient::builder()
+            .max_message_size(128)
+            .connect("127.0.0.1", server.port())
+            .unwrap();
+
+        let error = client.eval("(apply str (repeat 200 \"x\"))").err().unwrap();
+        assert!(matches!(
+            error,
+            NreplError::LimitExceeded {
+                setting: "max_message_size",
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "max_message_size exceeded (limit 128 bytes)"
+        );
+        let write = write_message(&client.writer, Codec::Bencode, &Message::new());
+        assert!(matches!(write, Err(NreplError::ConnectionClosed)));
+    }
+
+    #[test]
+    fn test_eval_collects_output_and_errors() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(boom)",
+            Reply::new()
+                .out("a")
+                .out("b\n")
+                .err("oops")
+                .status(&["eval-error", "error"])
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let result = client.eval("(boom)").unwrap();
+        assert_eq!(result.output, "ab\n");
+        assert_eq!(result.error, "oops");
+        assert!(result.has_error);
+        assert_eq!(result.value(), None);
+    }
+
+    #[test]
+    fn test_split_packets_are_reassembled() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(+ 1 2 3)", Reply::new().out("six\n").value("6").done());
+        server.set_chunk_size(Some(3));
+        let mut client = connect(&server);
+
+        let result = client.eval("(+ 1 2 3)").unwrap();
+        assert_eq!(result.output, "six\n");
+        assert_eq!(result.value(), Some("6"));
+    }
+
+    #[test]
+    fn test_describe_answered_while_eval_in_flight() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(slow)",
+            Reply::new()
+                .delay(Duration::from_millis(200))
+                .value("42")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let mut eval = client.start_eval("(slow)").unwrap();
+        let mut describe = client.start_describe().unwrap();
+
+        let description = describe.wait(Duration::from_secs(5)).unwrap();
+        assert!(ServerDescription::from_response(&description[0]).supports_op("eval"));
+        assert!(!eval.is_done());
+
+        let responses = eval.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(string_field(&responses[0], "value"), Some("42".to_string()));
+        assert!(eval.is_done());
+    }
+
+    #[test]
+    fn test_interleaved_responses_are_routed_by_id() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "1",
+            Reply::new()
+                .delay(Duration::from_millis(150))
+                .value("first")
+                .done(),
+        );
+        server.on_eval(
+            "2",
+            Reply::new()
+                .delay(Duration::from_millis(50))
+                .value("second")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let mut first = client.start_eval("1").unwrap();
+        let mut second = client.start_eval("2").unwrap();
+
+        let first_responses = first.wait(Duration::from_secs(5)).unwrap();
+        let second_responses = second.wait(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            string_field(&first_responses[0], "value"),
+            Some("first".to_string())
+        );
+        assert_eq!(
+            string_field(&second_responses[0], "value"),
+            Some("second".to_string())
+        );
+    }
+
+    #[test]
+    fn test_poll_and_cancel() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "slow",
+            Reply::new().delay(Duration::from_secs(5)).value("1").done(),
+        );
+        server.on_eval("fast", Reply::new().value("1").done());
+        let mut client = connect(&server);
+
+        let mut slow = client.start_eval("slow").unwrap();
+        assert!(slow.poll().unwrap().is_none());
+        slow.cancel();
+
+        let result = client
+            .eval_with_timeout("fast", Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(result.value(), Some("1"));
+    }
+
+    #[test]
+    fn test_timed_out_eval_does_not_leak_into_next() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "slow",
+            Reply::new()
+                .delay(Duration::from_millis(100))
+                .value("stale")
+                .done(),
+        );
+        server.on_eval(
+            "fast",
+            Reply::new()
+                .delay(Duration::from_millis(100))
+                .value("fresh")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let result = client.eval_with_timeout("slow", Duration::from_millis(50));
+        assert!(matches!(result, Err(NreplError::Timeout)));
+
+        let result = client.eval("fast").unwrap();
+        assert_eq!(result.value(), Some("fresh"));
+    }
+
+    #[test]
+    fn test_pending_requests_fail_when_connection_drops() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(+ 1 1)", Reply::new().disconnect());
+        let mut client = connect(&server);
+
+        let mut pending = client.start_eval("(+ 1 1)").unwrap();
+        assert!(matches!(
+            pending.next_response(Duration::from_secs(5)),
+            Err(NreplError::ConnectionClosed)
+        ));
+        assert!(matches!(
+            client.start_describe(),
+            Err(NreplError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_server_disconnect_fails_eval() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(slow)", Reply::new().delay(Duration::from_secs(5)).done());
+        let mut client = connect(&server);
+
+        let mut pending = client.start_eval("(slow)").unwrap();
+        server.disconnect_all();
+        assert!(matches!(
+            pending.next_response(Duration::from_secs(5)),
+            Err(NreplError::ConnectionClosed)
+        ));
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_interrupt_stops_running_eval() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(loop [] (recur))",
+            Reply::new().delay(Duration::from_secs(5)).done(),
+        );
+        let mut client = connect(&server);
+
+        client.clone_session().unwrap();
+        assert_eq!(client.interrupt().unwrap(), InterruptOutcome::SessionIdle);
+
+        let mut pending = client.start_eval("(loop [] (recur))").unwrap();
+        assert_eq!(client.interrupt().unwrap(), InterruptOutcome::Interrupted);
+
+        let responses = pending.wait(Duration::from_secs(5)).unwrap();
+        assert!(has_status(responses.last().unwrap(), "interrupted"));
+    }
+
+    #[test]
+    fn test_interrupt_eval_targets_request_id() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(loop [] (recur))",
+            Reply::new().delay(Duration::from_secs(5)).done(),
+        );
+        let mut client = connect(&server);
+
+        let stream = client
+            .eval_stream("(loop [] (recur))", Duration::from_secs(5))
+            .unwrap();
+        let session = stream.session().unwrap().to_string();
+
+        let outcome = client.interrupt_eval(&session, "some-other-eval").unwrap();
+        assert_eq!(outcome, InterruptOutcome::IdMismatch);
+
+        let outcome = client.interrupt_eval(&session, stream.id()).unwrap();
+        assert_eq!(outcome, InterruptOutcome::Interrupted);
+
+        let result = stream.into_result(|_| {}).unwrap();
+        assert!(result.interrupted);
+    }
+
+    #[test]
+    fn test_timeout_interrupts_eval_when_enabled() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(Thread/sleep 10000)",
+            Reply::new().delay(Duration::from_secs(10)).done(),
+        );
+        let mut client = connect(&server);
+        client.set_interrupt_on_timeout(true);
+
+        let result = client.eval_with_timeout("(Thread/sleep 10000)", Duration::from_millis(50));
+        assert!(matches!(result, Err(NreplError::Timeout)));
+
+        let eval_id = server
+            .received_op("eval")
+            .and_then(|message| string_field(&message, "id"))
+            .unwrap();
+        let interrupt = server.received_op("interrupt").unwrap();
+        assert_eq!(string_field(&interrupt, "interrupt-id"), Some(eval_id));
+    }
+
+    #[test]
+    fn test_eval_stream_yields_output_before_completion() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(tail)",
+            Reply::new()
+                .out("line 1\n")
+                .delay(Duration::from_millis(300))
+                .out("line 2\n")
+                .value("nil")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let started = Instant::now();
+        let mut stream = client
+            .eval_stream("(tail)", Duration::from_secs(5))
+            .unwrap();
+        let first = stream.next().unwrap().unwrap();
+        assert_eq!(first, EvalEvent::Out("line 1\n".to_string()));
+        assert!(started.elapsed() < Duration::from_millis(300));
+
+        let rest: Vec<EvalEvent> = stream.map(|event| event.unwrap()).collect();
+        assert_eq!(
+            rest,
+            vec![
+                EvalEvent::Out("line 2\n".to_string()),
+                EvalEvent::Value("nil".to_string()),
+                EvalEvent::Ns("user".to_string()),
+                EvalEvent::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_eval_with_callback_reports_events_and_result() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(println \"hi\")",
+            Reply::new().out("hi\n").value("nil").done(),
+        );
+        let mut client = connect(&server);
+
+        let mut seen = Vec::new();
+        let result = client
+            .eval_with_callback("(println \"hi\")", Duration::from_secs(5), |event| {
+                seen.push(event.clone())
+            })
+            .unwrap();
+
+        assert_eq!(seen.first(), Some(&EvalEvent::Out("hi\n".to_string())));
+        assert_eq!(seen.last(), Some(&EvalEvent::Done));
+        assert_eq!(result.output, "hi\n");
+        assert_eq!(result.value(), Some("nil"));
+    }
+
+    #[test]
+    fn test_eval_stream_times_out() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(slow)", Reply::new().delay(Duration::from_secs(5)).done());
+        let mut client = connect(&server);
+
+        let mut stream = client
+            .eval_stream("(slow)", Duration::from_millis(50))
+            .unwrap();
+        assert!(matches!(stream.next(), Some(Err(NreplError::Timeout))));
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_multi_form_eval_keeps_every_value() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(def x 1) (in-ns 'other) (inc 1)",
+            Reply::new()
+                .value("#'user/x")
+                .message(&[
+                    ("value", Value::Bytes(b"#namespace[other]".to_vec())),
+                    ("ns", Value::Bytes(b"other".to_vec())),
+                ])
+                .message(&[
+                    ("value", Value::Bytes(b"2".to_vec())),
+                    ("ns", Value::Bytes(b"other".to_vec())),
+                ])
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let result = client.eval("(def x 1) (in-ns 'other) (inc 1)").unwrap();
+        let values: Vec<(&str, Option<&str>)> = result
+            .values
+            .iter()
+            .map(|v| (v.value.as_str(), v.ns.as_deref()))
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                ("#'user/x", Some("user")),
+                ("#namespace[other]", Some("other")),
+                ("2", Some("other")),
+            ]
+        );
+        assert_eq!(result.value(), Some("2"));
+    }
+
+    fn divide_by_zero_reply() -> Reply {
+        Reply::new()
+            .err("Execution error (ArithmeticException) at user/eval1 (REPL:1).\nDivide by zero\n")
+            .message(&[
+                (
+                    "ex",
+                    Value::Bytes(b"class java.lang.ArithmeticException".to_vec()),
+                ),
+                (
+                    "root-ex",
+                    Value::Bytes(b"class java.lang.ArithmeticException".to_vec()),
+                ),
+            ])
+            .status(&["eval-error"])
+            .done()
+    }
+
+    #[test]
+    fn test_eval_as_reads_value_into_rust_type() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(range 10)",
+            Reply::new().value("(0 1 2 3 4 5 6 7 8 9)").done(),
+        );
+        server.on_eval("(/ 1 0)", divide_by_zero_reply());
+        let mut client = connect(&server);
+
+        let numbers: Vec<i64> = client.eval_as("(range 10)").unwrap();
+        assert_eq!(numbers, (0..10).collect::<Vec<_>>());
+        assert!(matches!(
+            client.eval_as::<String>("(range 10)"),
+            Err(NreplError::ParseError(_))
+        ));
+        assert!(matches!(
+            client.eval_as::<i64>("(/ 1 0)"),
+            Err(NreplError::Other(msg)) if msg.contains("ArithmeticException")
+        ));
+    }
+
+    #[test]
+    fn test_eval_error_reports_exception() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(/ 1 0)", divide_by_zero_reply());
+        server.on_eval(
+            STACKTRACE_EVAL,
+            Reply::new()
+                .out("clojure.lang.Numbers.divide(Numbers.java:190)\n")
+                .out("user$eval1.invokeStatic(NO_SOURCE_FILE:1)\n")
+                .value("nil")
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let mut result = client.eval("(/ 1 0)").unwrap();
+        assert!(result.has_error);
+        let exception = result.exception.clone().unwrap();
+        assert_eq!(exception.class, "java.lang.ArithmeticException");
+        assert_eq!(
+            exception.root_class.as_deref(),
+            Some("java.lang.ArithmeticException")
+        );
+        assert!(exception.message.ends_with("Divide by zero"));
+        assert_eq!(exception.stacktrace, None);
+
+        client.fetch_stacktrace(&mut result).unwrap();
+        let frames = result.exception.unwrap().stacktrace.unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].name, "clojure.lang.Numbers.divide");
+        assert_eq!(frames[1].line, Some(1));
+    }
+
+    #[test]
+    fn test_fetch_stacktrace_prefers_stacktrace_op() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(/ 1 0)", divide_by_zero_reply());
+        server.on_op(
+            "describe",
+            Reply::new()
+                .message(&[(
+                    "ops",
+                    Value::Dict(HashMap::from([(
+                        b"stacktrace".to_vec(),
+                        Value::Dict(HashMap::new()),
+                    )])),
+                )])
+                .done(),
+        );
+        let frame = Value::Dict(HashMap::from([
+            (
+                b"name".to_vec(),
+                Value::Bytes(b"clojure.lang.Numbers/divide".to_vec()),
+            ),
+            (b"file".to_vec(), Value::Bytes(b"Numbers.java".to_vec())),
+            (b"line".to_vec(), Value::Int(190)),
+        ]));
+        server.on_op(
+            "stacktrace",
+            Reply::new()
+                .message(&[
+                    (
+                        "class",
+                        Value::Bytes(b"java.lang.ArithmeticException".to_vec()),
+                    ),
+                    ("stacktrace", Value::List(vec![frame])),
+                ])
+                .done(),
+        );
+        let mut client = connect(&server);
+
+        let mut result = client.eval("(/ 1 0)").unwrap();
+        client.fetch_stacktrace(&mut result).unwrap();
+        assert_eq!(
+            result.exception.unwrap().stacktrace,
+            Some(vec![StackFrame {
+                name: "clojure.lang.Numbers/divide".to_string(),
+                file: Some("Numbers.java".to_string()),
+                line: Some(190),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_fetch_stacktrace_uses_the_results_session() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(/ 1 0)", divide_by_zero_reply());
+        server.on_eval(STACKTRACE_EVAL, Reply::new().value("nil").done());
+        let mut client = connect(&server);
+        client.clone_session().unwrap();
+        let worker = client.new_session("worker").unwrap();
+
+        let mut result = client.eval_in_session("(/ 1 0)", "worker").unwrap();
+        assert_eq!(result.session.as_deref(), Some(worker.as_str()));
+        client.fetch_stacktrace(&mut result).unwrap();
+
+        let eval = server.received_op("eval").unwrap();
+        assert_eq!(
+            string_field(&eval, "code").as_deref(),
+            Some(STACKTRACE_EVAL)
+        );
+        assert_eq!(string_field(&eval, "session"), Some(worker));
+    }
+
+    #[test]
+    fn test_namespace_is_tracked_per_session() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(in-ns 'app.core)",
+            Reply::new()
+                .message(&[
+                    ("value", Value::Bytes(b"#namespace[app.core]".to_vec())),
+                    ("ns", Value::Bytes(b"app.core".to_vec())),
+                ])
+                .done(),
+        );
+        let mut client = connect(&server);
+        assert_eq!(client.current_ns(), None);
+
+        let result = client.eval("(in-ns 'app.core)").unwrap();
+        assert_eq!(result.ns.as_deref(), Some("app.core"));
+        assert_eq!(client.current_ns().as_deref(), Some("app.core"));
+
+        let session = client.session.clone().unwrap();
+        assert_eq!(client.session_ns(&session).as_deref(), Some("app.core"));
+    }
+
+    #[test]
+    fn test_eval_in_ns_sends_namespace() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("*ns*", Reply::new().value("#namespace[user]").done());
+        let mut client = connect(&server);
+
+        let result = client.eval_in_ns("*ns*", "user").unwrap();
+        assert_eq!(result.ns.as_deref(), Some("user"));
+
+        let eval = server.received_op("eval").unwrap();
+        assert_eq!(string_field(&eval, "ns").as_deref(), Some("user"));
+    }
+
+    #[test]
+    fn test_load_file_sends_contents_and_location() {
+        let server = MockServer::start().unwrap();
+        server.on_op("load-file", Reply::new().value("#'app.core/greet").done());
+        let dir = std::env::temp_dir().join(format!("nrepl-load-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("core.clj");
+        let source = "(ns app.core)\n(defn greet [] \"hi\")\n";
+        std::fs::write(&path, source).unwrap();
+        let mut client = connect(&server);
+
+        let result = client.load_file(&path).unwrap();
+        assert_eq!(result.value(), Some("#'app.core/greet"));
+
+        let request = server.received_op("load-file").unwrap();
+        assert_eq!(string_field(&request, "file").as_deref(), Some(source));
+        assert_eq!(
+            string_field(&request, "file-name").as_deref(),
+            Some("core.clj")
+        );
+        assert_eq!(
+            string_field(&request, "file-path"),
+            Some(path.to_string_lossy().to_string())
+        );
+        assert!(request.contains_key("session"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_missing_file_is_io_error() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+        assert!(matches!(
+            client.load_file("/nonexistent/core.clj"),
+            Err(NreplError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_eval_options_send_source_position() {
+        let server = MockServer::start().unwrap();
+        let mut client = connect(&server);
+
+        let options = EvalOptions::new()
+            .file("src/app/core.clj")
+            .line(12)
+            .column(3);
+        client
+            .eval_with_options("(boom)", Duration::from_secs(5), &options)
+            .unwrap();
+
+        let eval = server.received_op("eval").unwrap();
+        assert_eq!(
+            string_field(&eval, "file").as_deref(),
+            Some("src/app/core.clj")
+        );
+        assert_eq!(eval.get("line"), Some(&Value::Int(12)));
+        assert_eq!(eval.get("column"), Some(&Value::Int(3)));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::bencode::{BencodeDecoder, Message};
+use crate::client::NreplError;
+use crate::edn::{EdnDecoder, message_to_edn};
+
+/// The wire format messages are encoded in.
+///
+/// nREPL speaks bencode unless started with another transport, such as
+/// `--transport nrepl.transport/edn`. Client and server must agree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codec {
+    /// nREPL's default transport, `nrepl.transport/bencode`.
+    #[default]
+    Bencode,
+    /// `nrepl.transport/edn`: one EDN map per message.
+    Edn,
+}
+
+impl Codec {
+    /// Returns the fully qualified nREPL transport var, as passed to
+    /// `nrepl.cmdline --transport`.
+    pub fn transport_var(&self) -> &'static str {
+        match self {
+            Codec::Bencode => "nrepl.transport/bencode",
+            Codec::Edn => "nrepl.transport/edn",
+        }
+    }
+
+    /// Encodes a message for sending.
+    pub fn encode(&self, msg: &Message) -> Result<Vec<u8>, NreplError> {
+        match self {
+            Codec::Bencode => {
+                serde_bencode::to_bytes(msg).map_err(|e| NreplError::ParseError(e.to_string()))
+            }
+            Codec::Edn => Ok(message_to_edn(msg).to_string().into_bytes()),
+        }
+    }
+
+    /// Creates a decoder for a stream of messages in this format.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_message_size` - Maximum size of a single encoded message.
+    pub fn decoder(&self, max_message_size: usize) -> Box<dyn MessageDecoder> {
+        match self {
+            Codec::Bencode => {
+                let mut decoder = BencodeDecoder::new();
+                decoder.set_max_message_size(max_message_size);
+                Box::new(decoder)
+            }
+            Codec::Edn => {
+                let mut decoder = EdnDecoder::new();
+                decoder.set_max_message_size(max_message_size);
+                Box::new(decoder)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Codec {
+    type Err = NreplError;
+
+    /// Parses `bencode` or `edn`, with or without the `nrepl.transport/` prefix.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.strip_prefix("nrepl.transport/").unwrap_or(name) {
+            "bencode" => Ok(Codec::Bencode),
+            "edn" => Ok(Codec::Edn),
+            other => Err(NreplError::Other(format!("Unknown transport '{}'", other))),
+        }
+    }
+}
+
+/// Splits a byte stream into messages, whatever the wire format.
+pub trait MessageDecoder: Send {
+    /// Appends newly received bytes.
+    fn feed(&mut self, bytes: &[u8]);
+
+    /// Returns the next complete message, or `Ok(None)` if more bytes are needed.
+    fn next_message(&mut self) -> Result<Option<Message>, NreplError>;
+}
+
+impl MessageDecoder for BencodeDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        BencodeDecoder::feed(self, bytes)
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, NreplError> {
+        BencodeDecoder::next_message(self)
+    }
+}
+
+impl MessageDecoder for EdnDecoder {
+    fn feed(&mut self, bytes: &[u8]) {
+        EdnDecoder::feed(self, bytes)
+    }
+
+    fn next_message(&mut self) -> Result<Option<Message>, NreplError> {
+        EdnDecoder::next_message(self)
+    }
+}
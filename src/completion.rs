@@ -0,0 +1,140 @@
+use crate::bencode::Message;
+use serde_bencode::value::Value;
+use std::collections::HashMap;
+
+/// A single candidate returned by the `complete` op.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// The text to insert, such as `str/join` or `println`.
+    pub candidate: String,
+    pub kind: CompletionKind,
+    /// Namespace the candidate is defined in, when the server reports it.
+    pub ns: Option<String>,
+    pub doc: Option<String>,
+}
+
+/// What a completion candidate refers to, from the `type` field of the reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionKind {
+    Function,
+    Macro,
+    Var,
+    Namespace,
+    Class,
+    Keyword,
+    SpecialForm,
+    /// A type the client does not know about, kept verbatim.
+    Other(String),
+    /// The server sent no `type`.
+    Unknown,
+}
+
+impl CompletionKind {
+    fn parse(kind: &str) -> Self {
+        match kind {
+            "function" => CompletionKind::Function,
+            "macro" => CompletionKind::Macro,
+            "var" => CompletionKind::Var,
+            "namespace" => CompletionKind::Namespace,
+            "class" => CompletionKind::Class,
+            "keyword" => CompletionKind::Keyword,
+            "special-form" => CompletionKind::SpecialForm,
+            other => CompletionKind::Other(other.to_string()),
+        }
+    }
+}
+
+impl Completion {
+    fn from_dict(fields: &HashMap<Vec<u8>, Value>) -> Option<Self> {
+        let text = |key: &str| match fields.get(key.as_bytes()) {
+            Some(Value::Bytes(bytes)) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        };
+        Some(Completion {
+            candidate: text("candidate")?,
+            kind: text("type")
+                .map(|kind| CompletionKind::parse(&kind))
+                .unwrap_or(CompletionKind::Unknown),
+            ns: text("ns"),
+            doc: text("doc"),
+        })
+    }
+}
+
+/// Collects the candidates from every `complete` response.
+///
+/// Entries without a `candidate` are skipped.
+pub(crate) fn completions_from_responses(responses: &[Message]) -> Vec<Completion> {
+    responses
+        .iter()
+        .filter_map(|response| match response.get("completions") {
+            Some(Value::List(completions)) => Some(completions),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|completion| match completion {
+            Value::Dict(fields) => Completion::from_dict(fields),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(entries: &[(&str, &str)]) -> Value {
+        Value::Dict(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    (
+                        key.as_bytes().to_vec(),
+                        Value::Bytes(value.as_bytes().to_vec()),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parses_candidates() {
+        let response = Message::from([(
+            "completions".to_string(),
+            Value::List(vec![
+                candidate(&[
+                    ("candidate", "println"),
+                    ("type", "function"),
+                    ("ns", "clojure.core"),
+                    ("doc", "Same as print followed by (newline)"),
+                ]),
+                candidate(&[("candidate", "clojure.string"), ("type", "namespace")]),
+                candidate(&[("candidate", "java.lang.String"), ("type", "class")]),
+                candidate(&[("candidate", "*out*"), ("type", "var")]),
+                candidate(&[("candidate", ".toString"), ("type", "method")]),
+                candidate(&[("candidate", "foo")]),
+                candidate(&[("type", "var")]),
+            ]),
+        )]);
+
+        let completions = completions_from_responses(&[response]);
+        assert_eq!(completions.len(), 6);
+        assert_eq!(
+            completions[0],
+            Completion {
+                candidate: "println".to_string(),
+                kind: CompletionKind::Function,
+                ns: Some("clojure.core".to_string()),
+                doc: Some("Same as print followed by (newline)".to_string()),
+            }
+        );
+        assert_eq!(completions[1].kind, CompletionKind::Namespace);
+        assert_eq!(completions[2].kind, CompletionKind::Class);
+        assert_eq!(completions[3].kind, CompletionKind::Var);
+        assert_eq!(
+            completions[4].kind,
+            CompletionKind::Other("method".to_string())
+        );
+        assert_eq!(completions[5].kind, CompletionKind::Unknown);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::bencode::Message;
+use serde_bencode::value::Value;
+use std::collections::HashMap;
+
+/// The reply to a `describe` request: what the server can do and what it runs on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerDescription {
+    /// Supported ops keyed by op name.
+    pub ops: HashMap<String, OpInfo>,
+    pub versions: Versions,
+    /// Extra data added by middleware, such as `current-ns`.
+    pub aux: HashMap<String, Value>,
+}
+
+/// Documentation for a single op, as provided by the middleware that handles it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpInfo {
+    pub doc: Option<String>,
+    /// Required request keys and their descriptions.
+    pub requires: HashMap<String, String>,
+    /// Optional request keys and their descriptions.
+    pub optional: HashMap<String, String>,
+    /// Response keys and their descriptions.
+    pub returns: HashMap<String, String>,
+}
+
+/// Versions reported by the server. Any of them may be missing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Versions {
+    pub nrepl: Option<Version>,
+    pub clojure: Option<Version>,
+    pub java: Option<Version>,
+}
+
+/// A version entry such as `{"major" 1 "minor" 3 "version-string" "1.3.1"}`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Version {
+    pub major: Option<i64>,
+    pub minor: Option<i64>,
+    pub incremental: Option<i64>,
+    pub qualifier: Option<String>,
+    pub version_string: String,
+}
+
+impl ServerDescription {
+    /// Builds a description from a raw `describe` response.
+    ///
+    /// Missing or malformed fields are left empty rather than treated as errors,
+    /// since servers differ in how much they report.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The `describe` reply.
+    ///
+    /// # Returns
+    ///
+    /// Returns the parsed `ServerDescription`.
+    pub fn from_response(response: &Message) -> Self {
+        let ops = match response.get("ops") {
+            Some(Value::Dict(ops)) => ops
+                .iter()
+                .map(|(name, info)| {
+                    (
+                        String::from_utf8_lossy(name).into_owned(),
+                        OpInfo::from_value(info),
+                    )
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        let versions = match response.get("versions") {
+            Some(Value::Dict(versions)) => {
+                let version =
+                    |key: &str| versions.get(key.as_bytes()).and_then(Version::from_value);
+                Versions {
+                    nrepl: version("nrepl"),
+                    clojure: version("clojure"),
+                    java: version("java"),
+                }
+            }
+            _ => Versions::default(),
+        };
+
+        let aux = match response.get("aux") {
+            Some(Value::Dict(aux)) => aux
+                .iter()
+                .map(|(key, value)| (String::from_utf8_lossy(key).into_owned(), value.clone()))
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        ServerDescription { ops, versions, aux }
+    }
+
+    /// Returns `true` if the server handles the given op.
+    pub fn supports_op(&self, op: &str) -> bool {
+        self.ops.contains_key(op)
+    }
+
+    /// Returns the documentation for an op, if the server handles it.
+    pub fn op(&self, op: &str) -> Option<&OpInfo> {
+        self.ops.get(op)
+    }
+
+    /// Returns the names of all supported ops, sorted.
+    pub fn op_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.ops.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Returns an `aux` entry as a string, if present and a byte string.
+    pub fn aux_string(&self, key: &str) -> Option<String> {
+        match self.aux.get(key) {
+            Some(Value::Bytes(bytes)) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl OpInfo {
+    fn from_value(value: &Value) -> Self {
+        let Value::Dict(fields) = value else {
+            return OpInfo::default();
+        };
+        let strings = |key: &str| match fields.get(key.as_bytes()) {
+            Some(Value::Dict(entries)) => entries
+                .iter()
+                .filter_map(|(name, doc)| {
+                    Some((
+                        String::from_utf8_lossy(name).into_owned(),
+                        bytes_string(doc)?,
+                    ))
+                })
+                .collect(),
+            _ => HashMap::new(),
+        };
+
+        OpInfo {
+            doc: fields.get(b"doc".as_slice()).and_then(bytes_string),
+            requires: strings("requires"),
+            optional: strings("optional"),
+            returns: strings("returns"),
+        }
+    }
+}
+
+impl Version {
+    fn from_value(value: &Value) -> Option<Self> {
+        let Value::Dict(fields) = value else {
+            return None;
+        };
+        // Java reports its version parts as strings, nREPL and Clojure as integers
+        let number = |key: &str| match fields.get(key.as_bytes()) {
+            Some(Value::Int(n)) => Some(*n),
+            Some(Value::Bytes(bytes)) => std::str::from_utf8(bytes).ok()?.parse().ok(),
+            _ => None,
+        };
+        let qualifier = fields
+            .get(b"qualifier".as_slice())
+            .and_then(bytes_string)
+            .filter(|qualifier| !qualifier.is_empty());
+        let version_string = fields
+            .get(b"version-string".as_slice())
+            .and_then(bytes_string)
+            .unwrap_or_default();
+
+        Some(Version {
+            major: number("major"),
+            minor: number("minor"),
+            incremental: number("incremental"),
+            qualifier,
+            version_string,
+        })
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.version_string)
+    }
+}
+
+fn bytes_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    fn dict(entries: &[(&str, Value)]) -> Value {
+        Value::Dict(
+            entries
+                .iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parses_ops_versions_and_aux() {
+        let eval_info = dict(&[
+            ("doc", bytes("Evaluates code.")),
+            (
+                "requires",
+                dict(&[("code", bytes("The code to evaluate."))]),
+            ),
+            (
+                "optional",
+                dict(&[("ns", bytes("Namespace to evaluate in."))]),
+            ),
+            ("returns", dict(&[("value", bytes("The result."))])),
+        ]);
+        let response = Message::from([
+            (
+                "ops".to_string(),
+                dict(&[("eval", eval_info), ("clone", dict(&[]))]),
+            ),
+            (
+                "versions".to_string(),
+                dict(&[
+                    (
+                        "nrepl",
+                        dict(&[
+                            ("major", Value::Int(1)),
+                            ("minor", Value::Int(3)),
+                            ("incremental", Value::Int(1)),
+                            ("version-string", bytes("1.3.1")),
+                        ]),
+                    ),
+                    (
+                        "java",
+                        dict(&[
+                            ("major", bytes("21")),
+                            ("minor", bytes("0")),
+                            ("qualifier", bytes("")),
+                            ("version-string", bytes("21.0.1")),
+                        ]),
+                    ),
+                ]),
+            ),
+            ("aux".to_string(), dict(&[("current-ns", bytes("user"))])),
+        ]);
+
+        let description = ServerDescription::from_response(&response);
+        assert!(description.supports_op("eval"));
+        assert!(!description.supports_op("complete"));
+        assert_eq!(description.op_names(), vec!["clone", "eval"]);
+
+        let eval = description.op("eval").unwrap();
+        assert_eq!(eval.doc.as_deref(), Some("Evaluates code."));
+        assert_eq!(eval.requires["code"], "The code to evaluate.");
+        assert_eq!(eval.optional["ns"], "Namespace to evaluate in.");
+        assert_eq!(eval.returns["value"], "The result.");
+
+        let nrepl = description.versions.nrepl.as_ref().unwrap();
+        assert_eq!(
+            (nrepl.major, nrepl.minor, nrepl.incremental),
+            (Some(1), Some(3), Some(1))
+        );
+        let java = description.versions.java.as_ref().unwrap();
+        assert_eq!(java.major, Some(21));
+        assert_eq!(java.qualifier, None);
+        assert_eq!(java.to_string(), "21.0.1");
+        assert!(description.versions.clojure.is_none());
+
+        assert_eq!(
+            description.aux_string("current-ns"),
+            Some("user".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_fields_give_empty_description() {
+        let description = ServerDescription::from_response(&Message::new());
+        assert_eq!(description, ServerDescription::default());
+    }
+}
@@ -0,0 +1,1318 @@
+use crate::bencode::{DEFAULT_MAX_MESSAGE_SIZE, MAX_DEPTH, Message};
+use crate::client::NreplError;
+use num_bigint::BigInt;
+use serde::de::value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde_bencode::value::Value;
+use std::fmt;
+
+/// An EDN value, as read from or written to nREPL's EDN transport, or
+/// printed by Clojure as an eval result.
+///
+/// Maps and sets keep their entries in the order they were read, since EDN
+/// values containing floats can't be hashed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EdnValue {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    /// An integer written with the `N` suffix, or too large for `i64`.
+    BigInt(BigInt),
+    /// A ratio such as `1/3`, as numerator and denominator.
+    Ratio(BigInt, BigInt),
+    /// A decimal written with the `M` suffix, such as `1.10M`, kept as the
+    /// text before the `M` since `f64` can't hold it exactly.
+    BigDec(String),
+    Float(f64),
+    String(String),
+    Char(char),
+    /// A keyword, without its leading colon.
+    Keyword(String),
+    Symbol(String),
+    List(Vec<EdnValue>),
+    Vector(Vec<EdnValue>),
+    Map(Vec<(EdnValue, EdnValue)>),
+    Set(Vec<EdnValue>),
+    /// A tagged element such as `#inst "2024-01-01"`, tag without the `#`.
+    Tagged(String, Box<EdnValue>),
+    /// A value with metadata. The `^:key` and `^Tag` shorthands are read as
+    /// `{:key true}` and `{:tag Tag}`, and stacked metadata is merged.
+    WithMeta(Vec<(EdnValue, EdnValue)>, Box<EdnValue>),
+}
+
+impl EdnValue {
+    /// Parses a single EDN value.
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The EDN text; anything after the first value other than
+    ///   whitespace and comments is an error.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the value, or `NreplError::ParseError`
+    /// if the input is not valid EDN.
+    pub fn parse(input: &str) -> Result<EdnValue, NreplError> {
+        let mut parser = Parser::complete(input.as_bytes());
+        let value = parser.next_value().map_err(Failure::into_error)?;
+        parser.skip_whitespace().map_err(Failure::into_error)?;
+        if parser.pos < input.len() {
+            return Err(NreplError::ParseError(format!(
+                "unexpected input after EDN value at byte {}",
+                parser.pos
+            )));
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for EdnValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EdnValue::Nil => write!(f, "nil"),
+            EdnValue::Bool(b) => write!(f, "{}", b),
+            EdnValue::Int(n) => write!(f, "{}", n),
+            EdnValue::BigInt(n) => write!(f, "{}N", n),
+            EdnValue::Ratio(numerator, denominator) => write!(f, "{}/{}", numerator, denominator),
+            EdnValue::BigDec(digits) => write!(f, "{}M", digits),
+            EdnValue::Float(x) if x.is_nan() => write!(f, "##NaN"),
+            EdnValue::Float(x) if x.is_infinite() => {
+                write!(f, "{}", if *x > 0.0 { "##Inf" } else { "##-Inf" })
+            }
+            // Debug always includes a decimal point or exponent, as EDN needs
+            EdnValue::Float(x) => write!(f, "{:?}", x),
+            EdnValue::String(s) => write_string(f, s),
+            EdnValue::Char(c) => match c {
+                '\n' => write!(f, "\\newline"),
+                ' ' => write!(f, "\\space"),
+                '\t' => write!(f, "\\tab"),
+                '\r' => write!(f, "\\return"),
+                c => write!(f, "\\{}", c),
+            },
+            EdnValue::Keyword(k) => write!(f, ":{}", k),
+            EdnValue::Symbol(s) => write!(f, "{}", s),
+            EdnValue::List(items) => write_seq(f, "(", items, ")"),
+            EdnValue::Vector(items) => write_seq(f, "[", items, "]"),
+            EdnValue::Set(items) => write_seq(f, "#{", items, "}"),
+            EdnValue::Map(entries) => write_map(f, entries),
+            EdnValue::Tagged(tag, value) => write!(f, "#{} {}", tag, value),
+            EdnValue::WithMeta(meta, value) => {
+                write!(f, "^")?;
+                write_map(f, meta)?;
+                write!(f, " {}", value)
+            }
+        }
+    }
+}
+
+fn write_map(f: &mut fmt::Formatter<'_>, entries: &[(EdnValue, EdnValue)]) -> fmt::Result {
+    write!(f, "{{")?;
+    for (index, (key, value)) in entries.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{} {}", key, value)?;
+    }
+    write!(f, "}}")
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_seq(
+    f: &mut fmt::Formatter<'_>,
+    open: &str,
+    items: &[EdnValue],
+    close: &str,
+) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, "{}", close)
+}
+
+/// Why parsing stopped.
+#[derive(Debug)]
+enum Failure {
+    /// The input ended inside a value; more bytes may complete it.
+    Incomplete,
+    Invalid(String),
+}
+
+impl Failure {
+    fn into_error(self) -> NreplError {
+        match self {
+            Failure::Incomplete => {
+                NreplError::ParseError("unexpected end of EDN input".to_string())
+            }
+            Failure::Invalid(msg) => NreplError::ParseError(msg),
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    /// Whether the input is all there is, so its end also ends a token.
+    complete: bool,
+    /// How many values are being read inside one another.
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Creates a parser for the start of a stream that may continue.
+    fn new(input: &'a [u8]) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            complete: false,
+            depth: 0,
+        }
+    }
+
+    /// Creates a parser for input that won't grow.
+    fn complete(input: &'a [u8]) -> Self {
+        Parser {
+            input,
+            pos: 0,
+            complete: true,
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn invalid<T>(&self, what: &str) -> Result<T, Failure> {
+        Err(Failure::Invalid(format!("{} at byte {}", what, self.pos)))
+    }
+
+    /// Skips whitespace, commas, comments and `#_` discarded values.
+    fn skip_whitespace(&mut self) -> Result<(), Failure> {
+        while let Some(byte) = self.peek() {
+            match byte {
+                b' ' | b'\t' | b'\n' | b'\r' | b',' => self.pos += 1,
+                b';' => {
+                    while let Some(byte) = self.peek() {
+                        self.pos += 1;
+                        if byte == b'\n' {
+                            break;
+                        }
+                    }
+                }
+                b'#' if self.input.get(self.pos + 1) == Some(&b'_') => {
+                    self.pos += 2;
+                    self.next_value()?;
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_value(&mut self) -> Result<EdnValue, Failure> {
+        if self.depth == MAX_DEPTH {
+            return self.invalid("nesting too deep");
+        }
+        self.depth += 1;
+        let value = self.value();
+        self.depth -= 1;
+        value
+    }
+
+    fn value(&mut self) -> Result<EdnValue, Failure> {
+        self.skip_whitespace()?;
+        let Some(byte) = self.peek() else {
+            return Err(Failure::Incomplete);
+        };
+        match byte {
+            b'(' => Ok(EdnValue::List(self.sequence(b')')?)),
+            b'[' => Ok(EdnValue::Vector(self.sequence(b']')?)),
+            b'{' => self.map(),
+            b'"' => self.string(),
+            b'\\' => self.character(),
+            b':' => {
+                self.pos += 1;
+                let name = self.token()?;
+                if name.is_empty() {
+                    return self.invalid("empty keyword");
+                }
+                Ok(EdnValue::Keyword(name))
+            }
+            b'#' => self.dispatch(),
+            b'^' => self.metadata(),
+            b')' | b']' | b'}' => self.invalid(&format!("unmatched '{}'", byte as char)),
+            _ => {
+                let start = self.pos;
+                let token = self.token()?;
+                atom(&token).ok_or_else(|| {
+                    Failure::Invalid(format!("invalid token '{}' at byte {}", token, start))
+                })
+            }
+        }
+    }
+
+    /// Reads values up to the closing delimiter, consuming both delimiters.
+    fn sequence(&mut self, close: u8) -> Result<Vec<EdnValue>, Failure> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace()?;
+            match self.peek() {
+                None => return Err(Failure::Incomplete),
+                Some(byte) if byte == close => {
+                    self.pos += 1;
+                    return Ok(items);
+                }
+                Some(_) => items.push(self.next_value()?),
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<EdnValue, Failure> {
+        let start = self.pos;
+        let items = self.sequence(b'}')?;
+        if items.len() % 2 != 0 {
+            return Err(Failure::Invalid(format!(
+                "map literal at byte {} has an odd number of forms",
+                start
+            )));
+        }
+        let mut items = items.into_iter();
+        let mut entries = Vec::new();
+        while let (Some(key), Some(value)) = (items.next(), items.next()) {
+            entries.push((key, value));
+        }
+        Ok(EdnValue::Map(entries))
+    }
+
+    /// Reads `^meta value`, merging with any metadata `value` already has.
+    fn metadata(&mut self) -> Result<EdnValue, Failure> {
+        self.pos += 1;
+        let start = self.pos;
+        let meta = match self.next_value()? {
+            EdnValue::Map(entries) => entries,
+            key @ EdnValue::Keyword(_) => vec![(key, EdnValue::Bool(true))],
+            tag @ (EdnValue::Symbol(_) | EdnValue::String(_)) => {
+                vec![(EdnValue::Keyword("tag".to_string()), tag)]
+            }
+            _ => {
+                return Err(Failure::Invalid(format!(
+                    "metadata at byte {} must be a map, keyword, symbol or string",
+                    start
+                )));
+            }
+        };
+        let (mut merged, value) = match self.next_value()? {
+            EdnValue::WithMeta(inner, value) => (inner, value),
+            value => (Vec::new(), Box::new(value)),
+        };
+        // The outer metadata wins, as when Clojure applies it last
+        for (key, val) in meta {
+            merged.retain(|(existing, _)| *existing != key);
+            merged.push((key, val));
+        }
+        Ok(EdnValue::WithMeta(merged, value))
+    }
+
+    fn dispatch(&mut self) -> Result<EdnValue, Failure> {
+        match self.input.get(self.pos + 1) {
+            None => Err(Failure::Incomplete),
+            Some(b'{') => {
+                self.pos += 1;
+                Ok(EdnValue::Set(self.sequence(b'}')?))
+            }
+            Some(b'#') => {
+                self.pos += 2;
+                match self.token()?.as_str() {
+                    "Inf" => Ok(EdnValue::Float(f64::INFINITY)),
+                    "-Inf" => Ok(EdnValue::Float(f64::NEG_INFINITY)),
+                    "NaN" => Ok(EdnValue::Float(f64::NAN)),
+                    other => self.invalid(&format!("unknown symbolic value '##{}'", other)),
+                }
+            }
+            Some(byte) if byte.is_ascii_alphabetic() => {
+                self.pos += 1;
+                let tag = self.token()?;
+                let value = self.next_value()?;
+                Ok(EdnValue::Tagged(tag, Box::new(value)))
+            }
+            Some(_) => self.invalid("unsupported dispatch character"),
+        }
+    }
+
+    fn string(&mut self) -> Result<EdnValue, Failure> {
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        loop {
+            let Some(byte) = self.peek() else {
+                return Err(Failure::Incomplete);
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(escape) = self.peek() else {
+                        return Err(Failure::Incomplete);
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'"' => bytes.push(b'"'),
+                        b'\\' => bytes.push(b'\\'),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'b' => bytes.push(0x08),
+                        b'f' => bytes.push(0x0c),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        other => {
+                            return self.invalid(&format!("unknown escape '\\{}'", other as char));
+                        }
+                    }
+                }
+                byte => bytes.push(byte),
+            }
+        }
+        match String::from_utf8(bytes) {
+            Ok(s) => Ok(EdnValue::String(s)),
+            Err(_) => self.invalid("string is not valid UTF-8"),
+        }
+    }
+
+    /// Reads the escape following `\u`. Characters outside the Basic
+    /// Multilingual Plane are written as a surrogate pair, `\ud83d\ude00`.
+    fn unicode_escape(&mut self) -> Result<char, Failure> {
+        let high = self.hex_digits()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return match char::from_u32(high) {
+                Some(c) => Ok(c),
+                None => self.invalid("invalid unicode escape"),
+            };
+        }
+        match self.input.get(self.pos..self.pos + 2) {
+            None => return Err(Failure::Incomplete),
+            Some(b"\\u") => self.pos += 2,
+            Some(_) => return self.invalid("unpaired surrogate in unicode escape"),
+        }
+        let low = self.hex_digits()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return self.invalid("unpaired surrogate in unicode escape");
+        }
+        let code = 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00);
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.invalid("invalid unicode escape"),
+        }
+    }
+
+    /// Reads the four hex digits of a `\u` escape.
+    fn hex_digits(&mut self) -> Result<u32, Failure> {
+        let Some(digits) = self.input.get(self.pos..self.pos + 4) else {
+            return Err(Failure::Incomplete);
+        };
+        let code = std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok());
+        self.pos += 4;
+        match code {
+            Some(code) => Ok(code),
+            None => self.invalid("invalid unicode escape"),
+        }
+    }
+
+    fn character(&mut self) -> Result<EdnValue, Failure> {
+        self.pos += 1;
+        // The character right after the backslash may itself be a delimiter
+        let Some(first) = self.peek() else {
+            return Err(Failure::Incomplete);
+        };
+        let rest = self.token_from(self.pos + utf8_len(first))?;
+        let name = String::from_utf8_lossy(&self.input[self.pos..rest]).into_owned();
+        self.pos = rest;
+        let mut chars = name.chars();
+        let c = match (chars.next(), chars.next()) {
+            (Some(c), None) => c,
+            _ => match name.as_str() {
+                "newline" => '\n',
+                "space" => ' ',
+                "tab" => '\t',
+                "return" => '\r',
+                "backspace" => '\u{8}',
+                "formfeed" => '\u{c}',
+                _ if name.starts_with('u') && name.len() == 5 => {
+                    match u32::from_str_radix(&name[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                    {
+                        Some(c) => c,
+                        None => return self.invalid("invalid unicode character"),
+                    }
+                }
+                _ => return self.invalid(&format!("unknown character '\\{}'", name)),
+            },
+        };
+        Ok(EdnValue::Char(c))
+    }
+
+    /// Reads a symbol-like token up to the next delimiter.
+    fn token(&mut self) -> Result<String, Failure> {
+        let end = self.token_from(self.pos)?;
+        let token = String::from_utf8_lossy(&self.input[self.pos..end]).into_owned();
+        self.pos = end;
+        Ok(token)
+    }
+
+    /// Returns where the token continuing at `from` ends. Running out of a
+    /// stream is incomplete, since the token might go on.
+    fn token_from(&self, from: usize) -> Result<usize, Failure> {
+        let mut end = from;
+        loop {
+            match self.input.get(end) {
+                None if self.complete => return Ok(end.min(self.input.len())),
+                None => return Err(Failure::Incomplete),
+                Some(byte) if is_delimiter(*byte) => return Ok(end),
+                Some(_) => end += 1,
+            }
+        }
+    }
+}
+
+fn is_delimiter(byte: u8) -> bool {
+    matches!(
+        byte,
+        b' ' | b'\t' | b'\n' | b'\r' | b',' | b'(' | b')' | b'[' | b']' | b'{' | b'}' | b'"' | b';'
+    )
+}
+
+fn utf8_len(first: u8) -> usize {
+    match first {
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    }
+}
+
+/// Interprets a bare token as nil, a boolean, a number or a symbol.
+///
+/// Integers that don't fit an `i64` are read as bigints, as Clojure does.
+fn atom(token: &str) -> Option<EdnValue> {
+    match token {
+        "nil" => return Some(EdnValue::Nil),
+        "true" => return Some(EdnValue::Bool(true)),
+        "false" => return Some(EdnValue::Bool(false)),
+        _ => {}
+    }
+    let bytes = token.as_bytes();
+    let numeric = match bytes {
+        [b'+' | b'-', second, ..] => second.is_ascii_digit(),
+        [first, ..] => first.is_ascii_digit(),
+        [] => false,
+    };
+    if !numeric {
+        return Some(EdnValue::Symbol(token.to_string()));
+    }
+
+    let digits = token.strip_prefix('+').unwrap_or(token);
+    if let Some(decimal) = digits.strip_suffix('M') {
+        decimal.parse::<f64>().ok()?;
+        return Some(EdnValue::BigDec(decimal.to_string()));
+    }
+    if digits.contains(['.', 'e', 'E']) {
+        return digits.parse().ok().map(EdnValue::Float);
+    }
+    if let Some((numerator, denominator)) = digits.split_once('/') {
+        let numerator = parse_integer(numerator)?;
+        let denominator = parse_integer(denominator)?;
+        // Clojure rejects zero and signed denominators
+        if denominator.sign() != num_bigint::Sign::Plus {
+            return None;
+        }
+        return Some(EdnValue::Ratio(numerator, denominator));
+    }
+    if let Some(big) = digits.strip_suffix('N') {
+        return parse_integer(big).map(EdnValue::BigInt);
+    }
+    match digits.parse() {
+        Ok(n) => Some(EdnValue::Int(n)),
+        Err(_) => parse_integer(digits).map(EdnValue::BigInt),
+    }
+}
+
+/// Parses an optionally negative run of decimal digits.
+fn parse_integer(text: &str) -> Option<BigInt> {
+    let unsigned = text.strip_prefix('-').unwrap_or(text);
+    if unsigned.is_empty() || !unsigned.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Deserializes a Rust value from an EDN value.
+///
+/// Lists, vectors and sets read as sequences and maps as maps or structs.
+/// Keywords and symbols read as strings without their colon, so `{:a 1}`
+/// fills a struct field `a`; use `#[serde(rename_all = "kebab-case")]` for
+/// Clojure-style names. Tags and metadata are skipped and the value beneath
+/// them read. Ratios read as floats, bigints as whatever integer type
+/// they fit, and `M` decimals as strings such as `"1.10"`, so no digits are
+/// lost. An enum variant is read from a keyword, symbol or string, or
+/// from a single-entry map or tagged value for variants with data.
+///
+/// # Arguments
+///
+/// * `value` - The EDN value to convert.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the value, or `NreplError::ParseError` if
+/// the EDN doesn't have the shape `T` expects.
+pub fn from_edn<T: DeserializeOwned>(value: EdnValue) -> Result<T, NreplError> {
+    T::deserialize(value)
+}
+
+/// Parses EDN text and deserializes a Rust value from it.
+///
+/// ```
+/// use nrepl_client_server_demo::edn;
+///
+/// let numbers: Vec<i64> = edn::from_str("(0 1 2)").unwrap();
+/// assert_eq!(numbers, vec![0, 1, 2]);
+/// ```
+///
+/// # Arguments
+///
+/// * `input` - The EDN text, such as an eval's printed value.
+///
+/// # Returns
+///
+/// Returns a `Result` containing the value, or `NreplError::ParseError` if
+/// the text is not valid EDN or doesn't have the shape `T` expects.
+pub fn from_str<T: DeserializeOwned>(input: &str) -> Result<T, NreplError> {
+    from_edn(EdnValue::parse(input)?)
+}
+
+impl de::Error for NreplError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        NreplError::ParseError(msg.to_string())
+    }
+}
+
+impl<'de> IntoDeserializer<'de, NreplError> for EdnValue {
+    type Deserializer = EdnValue;
+
+    fn into_deserializer(self) -> EdnValue {
+        self
+    }
+}
+
+fn visit_seq<'de, V: Visitor<'de>>(
+    items: Vec<EdnValue>,
+    visitor: V,
+) -> Result<V::Value, NreplError> {
+    let mut seq = SeqDeserializer::new(items.into_iter());
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+}
+
+fn visit_map<'de, V: Visitor<'de>>(
+    entries: Vec<(EdnValue, EdnValue)>,
+    visitor: V,
+) -> Result<V::Value, NreplError> {
+    let mut map = MapDeserializer::new(entries.into_iter());
+    let value = visitor.visit_map(&mut map)?;
+    map.end()?;
+    Ok(value)
+}
+
+impl<'de> de::Deserializer<'de> for EdnValue {
+    type Error = NreplError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NreplError> {
+        match self {
+            EdnValue::Nil => visitor.visit_unit(),
+            EdnValue::Bool(b) => visitor.visit_bool(b),
+            EdnValue::Int(n) => visitor.visit_i64(n),
+            EdnValue::BigInt(n) => {
+                if let Ok(n) = i64::try_from(&n) {
+                    visitor.visit_i64(n)
+                } else if let Ok(n) = u64::try_from(&n) {
+                    visitor.visit_u64(n)
+                } else if let Ok(n) = i128::try_from(&n) {
+                    visitor.visit_i128(n)
+                } else if let Ok(n) = u128::try_from(&n) {
+                    visitor.visit_u128(n)
+                } else {
+                    visitor.visit_string(n.to_string())
+                }
+            }
+            EdnValue::Ratio(numerator, denominator) => {
+                let to_float = |n: &BigInt| n.to_string().parse::<f64>().unwrap_or(f64::NAN);
+                visitor.visit_f64(to_float(&numerator) / to_float(&denominator))
+            }
+            EdnValue::Float(x) => visitor.visit_f64(x),
+            EdnValue::String(s)
+            | EdnValue::Keyword(s)
+            | EdnValue::Symbol(s)
+            | EdnValue::BigDec(s) => visitor.visit_string(s),
+            EdnValue::Char(c) => visitor.visit_char(c),
+            EdnValue::List(items) | EdnValue::Vector(items) | EdnValue::Set(items) => {
+                visit_seq(items, visitor)
+            }
+            EdnValue::Map(entries) => visit_map(entries, visitor),
+            EdnValue::Tagged(_, value) | EdnValue::WithMeta(_, value) => {
+                value.deserialize_any(visitor)
+            }
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, NreplError> {
+        match self {
+            EdnValue::Nil => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, NreplError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, NreplError> {
+        match self {
+            EdnValue::Keyword(s) | EdnValue::Symbol(s) | EdnValue::String(s) => {
+                visitor.visit_enum(s.into_deserializer())
+            }
+            EdnValue::Map(entries) if entries.len() == 1 => visitor.visit_enum(
+                MapAccessDeserializer::new(MapDeserializer::new(entries.into_iter())),
+            ),
+            EdnValue::Tagged(tag, value) => {
+                let entry = (EdnValue::Symbol(tag), *value);
+                visitor.visit_enum(MapAccessDeserializer::new(MapDeserializer::new(
+                    std::iter::once(entry),
+                )))
+            }
+            EdnValue::WithMeta(_, value) => value.deserialize_enum(name, variants, visitor),
+            other => Err(NreplError::ParseError(format!(
+                "expected an enum variant, got {}",
+                other
+            ))),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+/// Writes an nREPL message as an EDN map with keyword keys, the form nREPL's
+/// EDN transport reads.
+pub(crate) fn message_to_edn(message: &Message) -> EdnValue {
+    let mut keys: Vec<&String> = message.keys().collect();
+    keys.sort();
+    EdnValue::Map(
+        keys.into_iter()
+            .map(|key| (key_to_edn(key.as_bytes()), value_to_edn(&message[key])))
+            .collect(),
+    )
+}
+
+fn key_to_edn(key: &[u8]) -> EdnValue {
+    let name = String::from_utf8_lossy(key).into_owned();
+    let keyword_safe = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "*+!-_?<>=./".contains(c));
+    if keyword_safe {
+        EdnValue::Keyword(name)
+    } else {
+        EdnValue::String(name)
+    }
+}
+
+fn value_to_edn(value: &Value) -> EdnValue {
+    match value {
+        Value::Int(n) => EdnValue::Int(*n),
+        Value::Bytes(bytes) => EdnValue::String(String::from_utf8_lossy(bytes).into_owned()),
+        Value::List(items) => EdnValue::Vector(items.iter().map(value_to_edn).collect()),
+        Value::Dict(fields) => {
+            let mut keys: Vec<&Vec<u8>> = fields.keys().collect();
+            keys.sort();
+            EdnValue::Map(
+                keys.into_iter()
+                    .map(|key| (key_to_edn(key), value_to_edn(&fields[key])))
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Reads an nREPL message from an EDN map.
+///
+/// Keywords, symbols and strings all become byte strings, so a `status` of
+/// `#{:done}` reads the same as bencode's `["done"]`. Collections become
+/// lists and `nil` entries are left out.
+pub(crate) fn edn_to_message(value: EdnValue) -> Result<Message, NreplError> {
+    let EdnValue::Map(entries) = value else {
+        return Err(NreplError::ParseError(format!(
+            "expected an EDN map, got {}",
+            value
+        )));
+    };
+    Ok(entries
+        .into_iter()
+        .filter_map(|(key, value)| Some((key_name(key), edn_to_value(value)?)))
+        .collect())
+}
+
+fn key_name(key: EdnValue) -> String {
+    match key {
+        EdnValue::Keyword(name) | EdnValue::Symbol(name) | EdnValue::String(name) => name,
+        other => other.to_string(),
+    }
+}
+
+fn edn_to_value(value: EdnValue) -> Option<Value> {
+    let text = |s: String| Value::Bytes(s.into_bytes());
+    Some(match value {
+        EdnValue::Nil => return None,
+        EdnValue::Int(n) => Value::Int(n),
+        EdnValue::String(s) | EdnValue::Keyword(s) | EdnValue::Symbol(s) => text(s),
+        EdnValue::Char(c) => text(c.to_string()),
+        EdnValue::Bool(_)
+        | EdnValue::Float(_)
+        | EdnValue::BigInt(_)
+        | EdnValue::Ratio(..)
+        | EdnValue::BigDec(_) => text(value.to_string()),
+        EdnValue::List(items) | EdnValue::Vector(items) | EdnValue::Set(items) => {
+            Value::List(items.into_iter().filter_map(edn_to_value).collect())
+        }
+        EdnValue::Map(entries) => Value::Dict(
+            entries
+                .into_iter()
+                .filter_map(|(key, value)| Some((key_name(key).into_bytes(), edn_to_value(value)?)))
+                .collect(),
+        ),
+        EdnValue::Tagged(_, value) | EdnValue::WithMeta(_, value) => return edn_to_value(*value),
+    })
+}
+
+/// Where the framing scanner is inside the form it is walking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    /// Between tokens, or inside a symbol, number or other bare token.
+    Code,
+    /// Inside a string literal.
+    String,
+    /// Right after a backslash inside a string literal.
+    StringEscape,
+    /// Right after the backslash that starts a character literal.
+    Char,
+    /// Inside a `;` comment.
+    Comment,
+}
+
+/// An incremental decoder for nREPL's EDN transport, which sends one EDN map
+/// per message with nothing between them.
+///
+/// A scanner tracks brackets, strings and comments across calls, and the
+/// buffered form is only parsed once it could be complete, so every byte is
+/// examined about twice however the stream is split.
+///
+/// A form that isn't valid EDN leaves no way to tell where the next one
+/// starts, so after a `ParseError` the decoder keeps returning it and the
+/// connection should be closed.
+pub struct EdnDecoder {
+    buffer: Vec<u8>,
+    /// Offset of the first byte of the form currently being framed.
+    start: usize,
+    /// Offset of the next byte the scanner has not looked at yet.
+    pos: usize,
+    /// Bracket nesting depth of the form being framed.
+    depth: usize,
+    state: ScanState,
+    /// Whether the scanner is inside a bare token at the top level.
+    in_token: bool,
+    /// Whether the form has started, as opposed to leading whitespace.
+    started: bool,
+    /// Why the stream could no longer be framed, once it can't.
+    failed: Option<String>,
+    max_message_size: usize,
+}
+
+impl Default for EdnDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EdnDecoder {
+    /// Creates an empty decoder with the default message size limit.
+    pub fn new() -> Self {
+        EdnDecoder {
+            buffer: Vec::new(),
+            start: 0,
+            pos: 0,
+            depth: 0,
+            state: ScanState::Code,
+            in_token: false,
+            started: false,
+            failed: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets the largest encoded message, in bytes, the decoder will buffer.
+    pub fn set_max_message_size(&mut self, max_message_size: usize) {
+        self.max_message_size = max_message_size;
+    }
+
+    /// Appends newly received bytes to the decoder's buffer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // Reclaim space taken by messages that were already handed out.
+        if self.start > 0 && self.start >= self.buffer.len() / 2 {
+            self.buffer.drain(..self.start);
+            self.pos -= self.start;
+            self.start = 0;
+        }
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Decodes the next complete message from the buffered bytes, if there is one.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(Some(message))` when a full message is available, `Ok(None)` when
+    /// more bytes are needed, an `NreplError::ParseError` if the input is not
+    /// a valid EDN map, or `NreplError::LimitExceeded` if a message is larger
+    /// than the size limit.
+    pub fn next_message(&mut self) -> Result<Option<Message>, NreplError> {
+        if let Some(msg) = &self.failed {
+            return Err(NreplError::ParseError(msg.clone()));
+        }
+        let (value, end) = match self.scan() {
+            Ok(Some(form)) => form,
+            Ok(None) => {
+                if self.buffer.len() - self.start > self.max_message_size {
+                    return Err(self.too_large());
+                }
+                return Ok(None);
+            }
+            Err(failure) => {
+                let error = failure.into_error();
+                self.failed = Some(error.to_string());
+                return Err(error);
+            }
+        };
+
+        let size = end - self.start;
+        self.start = end;
+        self.pos = end;
+        self.depth = 0;
+        self.state = ScanState::Code;
+        self.in_token = false;
+        self.started = false;
+        if self.start == self.buffer.len() {
+            self.buffer.clear();
+            self.start = 0;
+            self.pos = 0;
+        }
+
+        if size > self.max_message_size {
+            return Err(self.too_large());
+        }
+        edn_to_message(value).map(Some)
+    }
+
+    fn too_large(&self) -> NreplError {
+        NreplError::LimitExceeded {
+            setting: "max_message_size",
+            limit: format!("{} bytes", self.max_message_size),
+        }
+    }
+
+    /// Advances the scanner over the buffered bytes, parsing the form each
+    /// time a top-level token, string or bracket closes.
+    ///
+    /// Returns the first complete top-level form and the offset it ends at,
+    /// or `None` if the buffer ends before the form does.
+    fn scan(&mut self) -> Result<Option<(EdnValue, usize)>, Failure> {
+        while self.pos < self.buffer.len() {
+            let byte = self.buffer[self.pos];
+            self.pos += 1;
+            let closed = match self.state {
+                ScanState::String => {
+                    match byte {
+                        b'\\' => self.state = ScanState::StringEscape,
+                        b'"' => self.state = ScanState::Code,
+                        _ => {}
+                    }
+                    byte == b'"'
+                }
+                ScanState::StringEscape => {
+                    self.state = ScanState::String;
+                    false
+                }
+                ScanState::Char => {
+                    // Whatever follows the backslash is part of the character
+                    self.state = ScanState::Code;
+                    self.in_token = true;
+                    false
+                }
+                ScanState::Comment => {
+                    if byte == b'\n' {
+                        self.state = ScanState::Code;
+                    }
+                    false
+                }
+                ScanState::Code => {
+                    let token_ended = self.in_token && is_delimiter(byte);
+                    if token_ended {
+                        self.in_token = false;
+                    }
+                    match byte {
+                        b'"' => self.state = ScanState::String,
+                        b';' => self.state = ScanState::Comment,
+                        b'\\' => self.state = ScanState::Char,
+                        b'(' | b'[' | b'{' => {
+                            self.depth += 1;
+                            if self.depth > MAX_DEPTH {
+                                return Err(Failure::Invalid(format!(
+                                    "nesting too deep at byte {}",
+                                    self.pos - 1 - self.start
+                                )));
+                            }
+                        }
+                        // An unmatched bracket closes too, for the parser to report
+                        b')' | b']' | b'}' => self.depth = self.depth.saturating_sub(1),
+                        _ if !is_delimiter(byte) => self.in_token = true,
+                        _ => {}
+                    }
+                    token_ended || matches!(byte, b')' | b']' | b'}')
+                }
+            };
+
+            if !self.started {
+                let blank = matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | b',');
+                if self.state == ScanState::Comment || (self.state == ScanState::Code && blank) {
+                    // Drop whitespace and comments between messages
+                    self.start = self.pos;
+                    continue;
+                }
+                self.started = true;
+            }
+
+            if closed && self.depth == 0 {
+                let mut parser = Parser::new(&self.buffer[self.start..]);
+                match parser.next_value() {
+                    Ok(value) => return Ok(Some((value, self.start + parser.pos))),
+                    Err(Failure::Incomplete) => {}
+                    Err(invalid) => return Err(invalid),
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_parses_nrepl_reply() {
+        let value = EdnValue::parse(
+            r#"{:id "1", :status #{:done}, :value "[1 \"a\"]", :ns nil, :n 42, :x -1.5}"#,
+        )
+        .unwrap();
+        let message = edn_to_message(value).unwrap();
+        assert_eq!(message["id"], bytes("1"));
+        assert_eq!(message["status"], Value::List(vec![bytes("done")]));
+        assert_eq!(message["value"], bytes("[1 \"a\"]"));
+        assert_eq!(message["n"], Value::Int(42));
+        assert_eq!(message["x"], bytes("-1.5"));
+        assert!(!message.contains_key("ns"));
+    }
+
+    #[test]
+    fn test_parses_each_kind_of_value() {
+        let value = EdnValue::parse(
+            r#"(nil true \a \newline sym ns/sym #_ignored [1 2.0] #{:k} #inst "2024-01-01" ##Inf) ; done"#,
+        )
+        .unwrap();
+        assert_eq!(
+            value,
+            EdnValue::List(vec![
+                EdnValue::Nil,
+                EdnValue::Bool(true),
+                EdnValue::Char('a'),
+                EdnValue::Char('\n'),
+                EdnValue::Symbol("sym".to_string()),
+                EdnValue::Symbol("ns/sym".to_string()),
+                EdnValue::Vector(vec![EdnValue::Int(1), EdnValue::Float(2.0)]),
+                EdnValue::Set(vec![EdnValue::Keyword("k".to_string())]),
+                EdnValue::Tagged(
+                    "inst".to_string(),
+                    Box::new(EdnValue::String("2024-01-01".to_string()))
+                ),
+                EdnValue::Float(f64::INFINITY),
+            ])
+        );
+        assert_eq!(EdnValue::parse(" -42 ").unwrap(), EdnValue::Int(-42));
+        assert_eq!(
+            EdnValue::parse(":kw").unwrap(),
+            EdnValue::Keyword("kw".to_string())
+        );
+        assert!(EdnValue::parse("{:a}").is_err());
+        assert!(EdnValue::parse("[1 2").is_err());
+    }
+
+    #[test]
+    fn test_string_escapes_combine_surrogate_pairs() {
+        assert_eq!(
+            EdnValue::parse(r#""smile \ud83d\ude00 \u00e9""#).unwrap(),
+            EdnValue::String("smile \u{1f600} \u{e9}".to_string())
+        );
+        assert!(EdnValue::parse(r#""\ud83d""#).is_err());
+        assert!(EdnValue::parse(r#""\ud83d x""#).is_err());
+        assert!(EdnValue::parse(r#""\ud83d\u0041""#).is_err());
+        assert!(EdnValue::parse(r#""\ude00""#).is_err());
+
+        let mut decoder = EdnDecoder::new();
+        decoder.feed(br#"{:out "\ud83d"#);
+        assert!(decoder.next_message().unwrap().is_none());
+        decoder.feed(br#"\ude00"}"#);
+        assert_eq!(
+            decoder.next_message().unwrap().unwrap()["out"],
+            bytes("\u{1f600}")
+        );
+    }
+
+    #[test]
+    fn test_parses_bigints_ratios_and_metadata() {
+        let big: BigInt = "123456789012345678901234567890".parse().unwrap();
+        assert_eq!(
+            EdnValue::parse("123456789012345678901234567890").unwrap(),
+            EdnValue::BigInt(big.clone())
+        );
+        assert_eq!(
+            EdnValue::parse("7N").unwrap(),
+            EdnValue::BigInt(BigInt::from(7))
+        );
+        assert_eq!(
+            EdnValue::parse("-1/3").unwrap(),
+            EdnValue::Ratio(BigInt::from(-1), BigInt::from(3))
+        );
+        assert!(EdnValue::parse("1/0").is_err());
+        assert!(EdnValue::parse("1/-2").is_err());
+        assert_eq!(
+            EdnValue::parse("1.10M").unwrap(),
+            EdnValue::BigDec("1.10".to_string())
+        );
+        assert!(EdnValue::parse("1.x0M").is_err());
+
+        let value = EdnValue::parse(r#"^:private ^{:doc "x", :private false} ^String s"#).unwrap();
+        let keyword = |k: &str| EdnValue::Keyword(k.to_string());
+        assert_eq!(
+            value,
+            EdnValue::WithMeta(
+                vec![
+                    (keyword("tag"), EdnValue::Symbol("String".to_string())),
+                    (keyword("doc"), EdnValue::String("x".to_string())),
+                    (keyword("private"), EdnValue::Bool(true)),
+                ],
+                Box::new(EdnValue::Symbol("s".to_string())),
+            )
+        );
+
+        for text in [
+            "123456789012345678901234567890N",
+            "-1/3",
+            "3.14159265358979323846264338327950288M",
+            r#"^{:tag String, :doc "x"} s"#,
+        ] {
+            assert_eq!(EdnValue::parse(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Account {
+        user_name: String,
+        balance: f64,
+        tags: Vec<String>,
+        manager: Option<String>,
+        kind: Kind,
+    }
+
+    #[derive(Debug, PartialEq, serde::Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum Kind {
+        Personal,
+        Shared { owners: u8 },
+    }
+
+    #[test]
+    fn test_deserializes_rust_values() {
+        let numbers: Vec<i64> = from_str("(0 1 2 3)").unwrap();
+        assert_eq!(numbers, vec![0, 1, 2, 3]);
+
+        let account: Account = from_str(
+            r#"^{:line 1} {:user-name "ann", :balance 5/2, :tags #{:a b "c"}, :manager nil, :kind :personal}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            account,
+            Account {
+                user_name: "ann".to_string(),
+                balance: 2.5,
+                tags: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+                manager: None,
+                kind: Kind::Personal,
+            }
+        );
+        let kind: Kind = from_str("{:shared {:owners 2}}").unwrap();
+        assert_eq!(kind, Kind::Shared { owners: 2 });
+        let kind: Kind = from_str("#shared {:owners 3}").unwrap();
+        assert_eq!(kind, Kind::Shared { owners: 3 });
+
+        let decimals: Vec<String> = from_str("[1.10M 2M]").unwrap();
+        assert_eq!(decimals, vec!["1.10", "2"]);
+        let big: u128 = from_str("170141183460469231731687303715884105727N").unwrap();
+        assert_eq!(big, i128::MAX as u128);
+        let pairs: std::collections::HashMap<String, (char, bool)> =
+            from_str(r#"{:x [\a true]}"#).unwrap();
+        assert_eq!(pairs["x"], ('a', true));
+
+        assert!(from_str::<Vec<i64>>("[1 :two]").is_err());
+        assert!(from_str::<u8>("300").is_err());
+        assert!(from_str::<(i64, i64)>("[1 2 3]").is_err());
+    }
+
+    #[test]
+    fn test_message_round_trips_through_edn() {
+        let message = Message::from([
+            ("op".to_string(), bytes("eval")),
+            ("code".to_string(), bytes("(println \"hi\\n\")")),
+            ("line".to_string(), Value::Int(3)),
+            (
+                "nested".to_string(),
+                Value::Dict([(b"k".to_vec(), Value::List(vec![bytes("v")]))].into()),
+            ),
+        ]);
+        let text = message_to_edn(&message).to_string();
+        assert!(text.starts_with("{:code \"(println \\\"hi\\\\n\\\")\""));
+        let parsed = edn_to_message(EdnValue::parse(&text).unwrap()).unwrap();
+        assert_eq!(parsed, message);
+    }
+
+    #[test]
+    fn test_decoder_frames_split_and_joined_messages() {
+        let mut decoder = EdnDecoder::new();
+        let stream = b"{:id \"1\" :value \"{:a 1}\"}\n{:id \"2\" :status #{:done}}";
+        decoder.feed(&stream[..10]);
+        assert!(decoder.next_message().unwrap().is_none());
+        decoder.feed(&stream[10..]);
+        assert_eq!(decoder.next_message().unwrap().unwrap()["id"], bytes("1"));
+        assert_eq!(decoder.next_message().unwrap().unwrap()["id"], bytes("2"));
+        assert!(decoder.next_message().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decoder_frames_a_large_message_fed_byte_by_byte() {
+        let value = "a \"quoted\" ] \\ }".repeat(10_000);
+        let mut text = String::from("; brackets in comments ] don't count\n");
+        text.push_str("{:id \"1\" :chars [\\) \\\" \\]] :value ");
+        text.push_str(&EdnValue::String(value.clone()).to_string());
+        text.push_str(" :items [");
+        for n in 0..10_000 {
+            text.push_str(&format!("{{:n {}}} ", n));
+        }
+        text.push_str("]} #_{:id \"skipped\"} {:id \"2\"}");
+
+        let mut decoder = EdnDecoder::new();
+        let mut messages = Vec::new();
+        for byte in text.as_bytes() {
+            decoder.feed(std::slice::from_ref(byte));
+            while let Some(message) = decoder.next_message().unwrap() {
+                messages.push(message);
+            }
+        }
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["value"], bytes(&value));
+        assert_eq!(
+            messages[0]["chars"],
+            Value::List(vec![bytes(")"), bytes("\""), bytes("]")])
+        );
+        let Value::List(items) = &messages[0]["items"] else {
+            panic!("expected a list");
+        };
+        assert_eq!(items.len(), 10_000);
+        assert_eq!(messages[1]["id"], bytes("2"));
+    }
+
+    #[test]
+    fn test_deep_nesting_is_rejected() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(EdnValue::parse(&nested(MAX_DEPTH)).is_ok());
+        let err = EdnValue::parse(&nested(100_000)).unwrap_err();
+        assert!(err.to_string().contains("nesting too deep"), "{}", err);
+        let err = EdnValue::parse(&"^:m ".repeat(100_000)).unwrap_err();
+        assert!(err.to_string().contains("nesting too deep"), "{}", err);
+
+        let mut decoder = EdnDecoder::new();
+        decoder.feed(format!("{{:a {}", "[".repeat(100_000)).as_bytes());
+        let err = decoder.next_message().unwrap_err();
+        assert!(err.to_string().contains("nesting too deep"), "{}", err);
+    }
+
+    #[test]
+    fn test_decoder_stays_failed_after_invalid_input() {
+        let mut decoder = EdnDecoder::new();
+        decoder.feed(b"{:id \"1\" :value #=}{:id \"2\"}");
+        assert!(matches!(
+            decoder.next_message(),
+            Err(NreplError::ParseError(_))
+        ));
+        decoder.feed(b"{:id \"3\"}");
+        assert!(matches!(
+            decoder.next_message(),
+            Err(NreplError::ParseError(_))
+        ));
+    }
+}
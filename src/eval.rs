@@ -0,0 +1,549 @@
+use crate::bencode::Message;
+use crate::client::{
+    Codec, NreplError, PendingRequest, StdinProvider, string_field, write_message,
+};
+use crate::message::{Request, RequestMessage};
+use crate::transport::SharedWriter;
+use serde_bencode::value::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct EvalResult {
+    pub values: Vec<EvalValue>,
+    pub output: String,
+    pub error: String,
+    pub has_error: bool,
+    pub exception: Option<EvalException>,
+    /// The namespace the session was in when evaluation finished.
+    pub ns: Option<String>,
+    /// Whether the evaluation was stopped by an `interrupt`.
+    pub interrupted: bool,
+    /// The session the code was evaluated in.
+    pub session: Option<String>,
+}
+
+/// Optional fields sent along with an `eval` request.
+#[derive(Debug, Clone, Default)]
+pub struct EvalOptions {
+    /// Namespace to evaluate in instead of the session's current one.
+    pub ns: Option<String>,
+    /// Source file the code came from, reported in metadata and stack traces.
+    pub file: Option<String>,
+    /// Line in `file` where the code starts.
+    pub line: Option<i64>,
+    /// Column in `file` where the code starts.
+    pub column: Option<i64>,
+    /// Session to evaluate in, by name or ID, instead of the active one.
+    pub session: Option<String>,
+}
+
+impl EvalOptions {
+    /// Creates options that add nothing to the request.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluates in the given namespace.
+    pub fn ns(mut self, ns: &str) -> Self {
+        self.ns = Some(ns.to_string());
+        self
+    }
+
+    /// Attributes the code to the given source file.
+    pub fn file(mut self, file: &str) -> Self {
+        self.file = Some(file.to_string());
+        self
+    }
+
+    /// Sets the line the code starts at in its source file.
+    pub fn line(mut self, line: i64) -> Self {
+        self.line = Some(line);
+        self
+    }
+
+    /// Sets the column the code starts at in its source file.
+    pub fn column(mut self, column: i64) -> Self {
+        self.column = Some(column);
+        self
+    }
+
+    /// Evaluates in the given session, by name or ID.
+    pub fn session(mut self, session: &str) -> Self {
+        self.session = Some(session.to_string());
+        self
+    }
+
+    /// Builds the eval request for `code` with these options.
+    ///
+    /// `session` is left out; the client resolves session names itself.
+    pub(crate) fn to_request(&self, code: &str) -> RequestMessage {
+        RequestMessage::new(Request::Eval {
+            code: code.to_string(),
+            ns: self.ns.clone(),
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+        })
+    }
+}
+
+/// The printed value of one top-level form, with the namespace it was produced in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalValue {
+    pub value: String,
+    pub ns: Option<String>,
+}
+
+/// An exception thrown while evaluating code.
+///
+/// Class names come from the `ex` and `root-ex` fields nREPL sends with the
+/// `eval-error` status; the message is what the server printed to `*err*`.
+/// The stack trace is only present after
+/// [`NreplClient::fetch_stacktrace`](crate::client::NreplClient::fetch_stacktrace).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalException {
+    pub class: String,
+    pub root_class: Option<String>,
+    pub message: String,
+    pub stacktrace: Option<Vec<StackFrame>>,
+}
+
+/// One frame of an exception's stack trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    pub name: String,
+    pub file: Option<String>,
+    pub line: Option<i64>,
+}
+
+impl StackFrame {
+    /// Parses a frame as printed by `StackTraceElement.toString()`,
+    /// e.g. `clojure.lang.Numbers.divide(Numbers.java:190)`.
+    pub fn parse(frame: &str) -> StackFrame {
+        let frame = frame.trim();
+        let Some((name, location)) = frame.split_once('(') else {
+            return StackFrame {
+                name: frame.to_string(),
+                file: None,
+                line: None,
+            };
+        };
+        let location = location.trim_end_matches(')');
+        let (file, line) = match location.rsplit_once(':') {
+            Some((file, line)) => (file, line.parse().ok()),
+            None => (location, None),
+        };
+        StackFrame {
+            name: name.to_string(),
+            file: Some(file.to_string()).filter(|f| !f.is_empty()),
+            line,
+        }
+    }
+}
+
+/// Strips the `class ` prefix nREPL puts in front of exception class names.
+fn exception_class(name: &str) -> String {
+    name.strip_prefix("class ").unwrap_or(name).to_string()
+}
+
+impl EvalResult {
+    /// Returns the value of the last evaluated form, if any form produced one.
+    pub fn value(&self) -> Option<&str> {
+        self.values.last().map(|v| v.value.as_str())
+    }
+
+    /// Folds one eval event into the result.
+    pub(crate) fn apply(&mut self, event: &EvalEvent) {
+        match event {
+            EvalEvent::Out(out) => self.output.push_str(out),
+            EvalEvent::Err(err) => self.error.push_str(err),
+            EvalEvent::Value(value) => self.values.push(EvalValue {
+                value: value.clone(),
+                ns: None,
+            }),
+            // nREPL sends `ns` alongside the `value` it belongs to
+            EvalEvent::Ns(ns) => {
+                self.ns = Some(ns.clone());
+                if let Some(last) = self.values.last_mut()
+                    && last.ns.is_none()
+                {
+                    last.ns = Some(ns.clone());
+                }
+            }
+            EvalEvent::Exception { class, root_class } => {
+                self.has_error = true;
+                self.exception = Some(EvalException {
+                    class: class.clone(),
+                    root_class: root_class.clone(),
+                    message: String::new(),
+                    stacktrace: None,
+                });
+            }
+            EvalEvent::Status(statuses) => {
+                if statuses
+                    .iter()
+                    .any(|status| status == "error" || status == "eval-error")
+                {
+                    self.has_error = true;
+                }
+                if statuses.iter().any(|status| status == "interrupted") {
+                    self.interrupted = true;
+                }
+            }
+            EvalEvent::Done => {
+                // The error text has fully arrived once the eval is done
+                if let Some(exception) = &mut self.exception {
+                    exception.message = self.error.trim().to_string();
+                }
+            }
+        }
+    }
+}
+
+/// A single piece of an evaluation's progress, in the order the server sent it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalEvent {
+    /// Text the evaluated code printed to `*out*`.
+    Out(String),
+    /// Text the evaluated code printed to `*err*`.
+    Err(String),
+    /// The printed value of an evaluated form.
+    Value(String),
+    /// The namespace the value was produced in.
+    Ns(String),
+    /// An exception was thrown; carries the `ex` and `root-ex` class names.
+    Exception {
+        class: String,
+        root_class: Option<String>,
+    },
+    /// Statuses other than `done`, such as `eval-error` or `need-input`.
+    Status(Vec<String>),
+    /// The evaluation has completed; no further events follow.
+    Done,
+}
+
+impl EvalEvent {
+    /// Splits one eval response message into events.
+    ///
+    /// A message can carry several fields at once; they are reported in the
+    /// order `out`, `err`, `value`, `ns`, `ex`, `status`, and `Done` always comes last.
+    pub fn from_response(response: &Message) -> Vec<EvalEvent> {
+        let mut events = Vec::new();
+
+        if let Some(out) = string_field(response, "out") {
+            events.push(EvalEvent::Out(out));
+        }
+        if let Some(err) = string_field(response, "err") {
+            events.push(EvalEvent::Err(err));
+        }
+        if let Some(value) = string_field(response, "value") {
+            events.push(EvalEvent::Value(value));
+        }
+        if let Some(ns) = string_field(response, "ns") {
+            events.push(EvalEvent::Ns(ns));
+        }
+        if let Some(ex) = string_field(response, "ex") {
+            events.push(EvalEvent::Exception {
+                class: exception_class(&ex),
+                root_class: string_field(response, "root-ex").map(|root| exception_class(&root)),
+            });
+        }
+
+        let mut done = false;
+        if let Some(Value::List(items)) = response.get("status") {
+            let mut statuses = Vec::new();
+            for item in items {
+                if let Value::Bytes(bytes) = item {
+                    match bytes.as_slice() {
+                        b"done" => done = true,
+                        other => statuses.push(String::from_utf8_lossy(other).to_string()),
+                    }
+                }
+            }
+            if !statuses.is_empty() {
+                events.push(EvalEvent::Status(statuses));
+            }
+        }
+        if done {
+            events.push(EvalEvent::Done);
+        }
+
+        events
+    }
+}
+
+/// An iterator over the events of a single evaluation.
+///
+/// Returned by [`NreplClient::eval_stream`](crate::client::NreplClient::eval_stream).
+/// Each call to `next` blocks until the next event arrives or the evaluation's
+/// timeout expires. Iteration ends after `EvalEvent::Done` or after an error.
+/// A `need-input` status is answered from the client's stdin provider when the
+/// event is yielded.
+pub struct EvalStream {
+    pending: PendingRequest,
+    queued: VecDeque<EvalEvent>,
+    deadline: Instant,
+    finished: bool,
+    tracker: Option<NsTracker>,
+    stdin: Option<StdinResponder>,
+}
+
+/// Records the namespaces an eval reports against the session it ran in.
+pub(crate) struct NsTracker {
+    pub(crate) session: String,
+    pub(crate) namespaces: Arc<Mutex<HashMap<String, String>>>,
+}
+
+/// Answers `need-input` statuses for the session an eval runs in.
+pub(crate) struct StdinResponder {
+    pub(crate) session: String,
+    pub(crate) writer: SharedWriter,
+    pub(crate) codec: Codec,
+    pub(crate) provider: Arc<Mutex<Option<StdinProvider>>>,
+}
+
+impl StdinResponder {
+    /// Asks the provider for input and sends it with the `stdin` op.
+    ///
+    /// The server's acknowledgement carries an unregistered `id` and is dropped
+    /// by the reader thread.
+    fn respond(&self) -> Result<(), NreplError> {
+        let input = match self.provider.lock().unwrap().as_mut() {
+            Some(provider) => provider().unwrap_or_default(),
+            None => String::new(),
+        };
+
+        let mut request =
+            RequestMessage::new(Request::Stdin { stdin: input }).in_session(&self.session);
+        request.id = Some(uuid::Uuid::new_v4().to_string());
+        write_message(&self.writer, self.codec, &request.to_message())
+    }
+}
+
+impl EvalStream {
+    pub(crate) fn new(
+        pending: PendingRequest,
+        timeout: Duration,
+        tracker: Option<NsTracker>,
+        stdin: Option<StdinResponder>,
+    ) -> Self {
+        EvalStream {
+            pending,
+            queued: VecDeque::new(),
+            deadline: Instant::now() + timeout,
+            finished: false,
+            tracker,
+            stdin,
+        }
+    }
+
+    /// Returns the message `id` of the underlying eval request.
+    pub fn id(&self) -> &str {
+        self.pending.id()
+    }
+
+    /// Returns the session the eval runs in, if any.
+    pub fn session(&self) -> Option<&str> {
+        self.pending.session()
+    }
+
+    /// Consumes the remaining events, passing each to `on_event`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the accumulated `EvalResult`,
+    /// or the first `NreplError` the stream yields.
+    pub fn into_result<F>(self, mut on_event: F) -> Result<EvalResult, NreplError>
+    where
+        F: FnMut(&EvalEvent),
+    {
+        let mut result = EvalResult {
+            session: self.session().map(str::to_string),
+            ..EvalResult::default()
+        };
+        for event in self {
+            let event = event?;
+            on_event(&event);
+            result.apply(&event);
+        }
+        Ok(result)
+    }
+}
+
+impl Iterator for EvalStream {
+    type Item = Result<EvalEvent, NreplError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.queued.pop_front() {
+                match &event {
+                    EvalEvent::Done => self.finished = true,
+                    EvalEvent::Ns(ns) => {
+                        if let Some(tracker) = &self.tracker {
+                            tracker
+                                .namespaces
+                                .lock()
+                                .unwrap()
+                                .insert(tracker.session.clone(), ns.clone());
+                        }
+                    }
+                    EvalEvent::Status(statuses) if statuses.iter().any(|s| s == "need-input") => {
+                        if let Some(stdin) = &self.stdin
+                            && let Err(e) = stdin.respond()
+                        {
+                            self.finished = true;
+                            return Some(Err(e));
+                        }
+                    }
+                    _ => {}
+                }
+                return Some(Ok(event));
+            }
+            if self.finished {
+                return None;
+            }
+
+            let remaining = self.deadline.saturating_duration_since(Instant::now());
+            match self.pending.next_response(remaining) {
+                Ok(response) => self.queued.extend(EvalEvent::from_response(&response)),
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    fn absorb(result: &mut EvalResult, response: &Message) {
+        for event in EvalEvent::from_response(response) {
+            result.apply(&event);
+        }
+    }
+
+    #[test]
+    fn test_events_from_response_order() {
+        let response = Message::from([
+            ("value".to_string(), bytes("3")),
+            ("ns".to_string(), bytes("user")),
+            ("out".to_string(), bytes("hi")),
+            (
+                "status".to_string(),
+                Value::List(vec![bytes("done"), bytes("eval-error")]),
+            ),
+        ]);
+
+        assert_eq!(
+            EvalEvent::from_response(&response),
+            vec![
+                EvalEvent::Out("hi".to_string()),
+                EvalEvent::Value("3".to_string()),
+                EvalEvent::Ns("user".to_string()),
+                EvalEvent::Status(vec!["eval-error".to_string()]),
+                EvalEvent::Done,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_accumulates_result() {
+        let mut result = EvalResult::default();
+        for event in [
+            EvalEvent::Out("a".to_string()),
+            EvalEvent::Out("b".to_string()),
+            EvalEvent::Err("e".to_string()),
+            EvalEvent::Value("1".to_string()),
+            EvalEvent::Ns("user".to_string()),
+            EvalEvent::Value("2".to_string()),
+            EvalEvent::Ns("other".to_string()),
+            EvalEvent::Status(vec!["error".to_string()]),
+            EvalEvent::Done,
+        ] {
+            result.apply(&event);
+        }
+
+        assert_eq!(result.output, "ab");
+        assert_eq!(result.error, "e");
+        assert_eq!(result.value(), Some("2"));
+        assert_eq!(
+            result.values,
+            vec![
+                EvalValue {
+                    value: "1".to_string(),
+                    ns: Some("user".to_string()),
+                },
+                EvalValue {
+                    value: "2".to_string(),
+                    ns: Some("other".to_string()),
+                },
+            ]
+        );
+        assert!(result.has_error);
+    }
+
+    #[test]
+    fn test_exception_collects_classes_and_message() {
+        let mut result = EvalResult::default();
+        let ex = Message::from([
+            ("ex".to_string(), bytes("class clojure.lang.ExceptionInfo")),
+            (
+                "root-ex".to_string(),
+                bytes("class java.lang.ArithmeticException"),
+            ),
+            ("status".to_string(), Value::List(vec![bytes("eval-error")])),
+        ]);
+        absorb(&mut result, &ex);
+        absorb(
+            &mut result,
+            &Message::from([(
+                "err".to_string(),
+                bytes("Execution error.\nDivide by zero\n"),
+            )]),
+        );
+        absorb(
+            &mut result,
+            &Message::from([("status".to_string(), Value::List(vec![bytes("done")]))]),
+        );
+
+        assert!(result.has_error);
+        assert_eq!(
+            result.exception,
+            Some(EvalException {
+                class: "clojure.lang.ExceptionInfo".to_string(),
+                root_class: Some("java.lang.ArithmeticException".to_string()),
+                message: "Execution error.\nDivide by zero".to_string(),
+                stacktrace: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_stack_frame() {
+        assert_eq!(
+            StackFrame::parse("clojure.lang.Numbers.divide(Numbers.java:190)"),
+            StackFrame {
+                name: "clojure.lang.Numbers.divide".to_string(),
+                file: Some("Numbers.java".to_string()),
+                line: Some(190),
+            }
+        );
+        assert_eq!(
+            StackFrame::parse("java.lang.Thread.run(Unknown Source)"),
+            StackFrame {
+                name: "java.lang.Thread.run".to_string(),
+                file: Some("Unknown Source".to_string()),
+                line: None,
+            }
+        );
+    }
+}
@@ -0,0 +1,22 @@
+#[cfg(feature = "tokio")]
+pub mod async_client;
+pub mod bencode;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+pub mod builder;
+pub mod client;
+pub mod codec;
+pub mod completion;
+pub mod describe;
+pub mod edn;
+pub mod eval;
+pub mod lookup;
+pub mod message;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod reconnect;
+mod routing;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
@@ -0,0 +1,183 @@
+use crate::bencode::Message;
+use serde_bencode::value::Value;
+
+/// Metadata about a var, class or special form, as returned by the `lookup` op.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymbolInfo {
+    pub name: String,
+    /// Namespace the symbol is defined in; `None` for classes and special forms.
+    pub ns: Option<String>,
+    /// One entry per arity, such as `["[x]", "[x & more]"]`.
+    pub arglists: Vec<String>,
+    pub docstring: Option<String>,
+    /// Source file as reported by the runtime, often a classpath-relative path.
+    pub file: Option<String>,
+    pub line: Option<i64>,
+    pub column: Option<i64>,
+}
+
+impl SymbolInfo {
+    /// Builds a `SymbolInfo` from the `lookup` replies.
+    ///
+    /// # Arguments
+    ///
+    /// * `responses` - Every response to the request, up to and including `done`.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if the server found nothing for the symbol.
+    pub(crate) fn from_responses(responses: &[Message]) -> Option<Self> {
+        let info = responses
+            .iter()
+            .find_map(|response| match response.get("info") {
+                Some(Value::Dict(info)) if !info.is_empty() => Some(info),
+                _ => None,
+            })?;
+
+        let text = |key: &str| match info.get(key.as_bytes()) {
+            Some(Value::Bytes(bytes)) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            _ => None,
+        };
+        // lookup stringifies most metadata, so numbers may arrive either way
+        let number = |key: &str| match info.get(key.as_bytes()) {
+            Some(Value::Int(n)) => Some(*n),
+            Some(Value::Bytes(bytes)) => std::str::from_utf8(bytes).ok()?.parse().ok(),
+            _ => None,
+        };
+        let arglists = match info.get(b"arglists".as_slice()) {
+            Some(Value::Bytes(bytes)) => split_arglists(&String::from_utf8_lossy(bytes)),
+            Some(Value::List(items)) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::Bytes(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Some(SymbolInfo {
+            name: text("name")?,
+            ns: text("ns"),
+            arglists,
+            docstring: text("doc"),
+            file: text("file"),
+            line: number("line"),
+            column: number("column"),
+        })
+    }
+}
+
+/// Splits a printed arglists form like `([x] [x & more])` into one string per arity.
+fn split_arglists(printed: &str) -> Vec<String> {
+    let inner = printed
+        .trim()
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .unwrap_or(printed);
+
+    let mut arities = Vec::new();
+    let mut depth = 0usize;
+    let mut start = None;
+    let mut chars = inner.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            // Brackets inside string and char literals are not structure
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\\' => {
+                chars.next();
+            }
+            '[' | '(' | '{' => {
+                if depth == 0 {
+                    start = Some(index);
+                }
+                depth += 1;
+            }
+            ']' | ')' | '}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0
+                    && let Some(begin) = start.take()
+                {
+                    arities.push(inner[begin..=index].to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    arities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(s: &str) -> Value {
+        Value::Bytes(s.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_parses_lookup_info() {
+        let info = Value::Dict(
+            [
+                ("name", bytes("map")),
+                ("ns", bytes("clojure.core")),
+                ("arglists", bytes("([f] [f coll] [f c1 c2 & colls])")),
+                ("doc", bytes("Returns a lazy sequence...")),
+                ("file", bytes("clojure/core.clj")),
+                ("line", Value::Int(2727)),
+                ("column", bytes("1")),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value))
+            .collect(),
+        );
+        let response = Message::from([("info".to_string(), info)]);
+
+        let symbol = SymbolInfo::from_responses(&[response]).unwrap();
+        assert_eq!(symbol.name, "map");
+        assert_eq!(symbol.ns.as_deref(), Some("clojure.core"));
+        assert_eq!(
+            symbol.arglists,
+            vec!["[f]", "[f coll]", "[f c1 c2 & colls]"]
+        );
+        assert_eq!(
+            symbol.docstring.as_deref(),
+            Some("Returns a lazy sequence...")
+        );
+        assert_eq!(symbol.file.as_deref(), Some("clojure/core.clj"));
+        assert_eq!(symbol.line, Some(2727));
+        assert_eq!(symbol.column, Some(1));
+    }
+
+    #[test]
+    fn test_empty_info_means_not_found() {
+        let response = Message::from([("info".to_string(), Value::Dict(Default::default()))]);
+        assert_eq!(SymbolInfo::from_responses(&[response]), None);
+    }
+
+    #[test]
+    fn test_split_arglists_handles_destructuring() {
+        assert_eq!(
+            split_arglists("([{:keys [a b]} [x & xs]] [])"),
+            vec!["[{:keys [a b]} [x & xs]]", "[]"]
+        );
+    }
+
+    #[test]
+    fn test_split_arglists_skips_string_and_char_literals() {
+        assert_eq!(
+            split_arglists(r#"([& {:keys [a] :or {a "]\""}}] [c \] d])"#),
+            vec![r#"[& {:keys [a] :or {a "]\""}}]"#, r"[c \] d]"]
+        );
+    }
+}
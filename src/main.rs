@@ -1,22 +1,72 @@
-mod client;
-mod server;
-
-use client::*;
-use server::*;
+use nrepl_client_server_demo::client::*;
+use nrepl_client_server_demo::server::*;
 use std::io;
+use std::io::{BufRead, Write};
 use std::thread;
 use std::time::Duration;
 
 use std::env;
 
-fn start_server() -> io::Result<()> {
+/// Where the client and REPL modes connect to.
+struct Target {
+    address: Address,
+    codec: Codec,
+}
+
+enum Address {
+    Port(u16),
+    Socket(String),
+}
+
+impl Target {
+    /// Parses `<port>` or `--socket <path>` from the arguments after the mode.
+    fn from_args(args: &[String], codec: Codec) -> Target {
+        let address = match args {
+            [flag, path, ..] if flag == "--socket" => Address::Socket(path.clone()),
+            [port, ..] => Address::Port(port.parse().expect("Failed to parse port to u16")),
+            [] => panic!("Expected a port or --socket <path>"),
+        };
+        Target { address, codec }
+    }
+
+    fn connect(&self) -> Result<NreplClient, NreplError> {
+        let builder = NreplClient::builder().codec(self.codec);
+        match &self.address {
+            Address::Port(port) => builder.connect("127.0.0.1", *port),
+            Address::Socket(path) => builder.connect_unix(path),
+        }
+    }
+}
+
+/// Removes `--transport <name>` from the arguments and returns the codec it names.
+fn take_transport(args: &mut Vec<String>) -> Codec {
+    let Some(index) = args.iter().position(|arg| arg == "--transport") else {
+        return Codec::Bencode;
+    };
+    let name = args.get(index + 1).expect("Expected a transport name");
+    let codec = name.parse().expect("Expected --transport bencode or edn");
+    args.drain(index..=index + 1);
+    codec
+}
+
+fn start_server(args: &[String], codec: Codec) -> io::Result<()> {
     println!("Starting nREPL server...");
 
     let mut server = NreplServer::new();
+    server.set_codec(codec);
 
-    match server.start_with_clj() {
-        Ok(port) => {
-            println!("nREPL server started successfully on port {}", port);
+    let started = match args {
+        [flag, path, ..] if flag == "--socket" => server
+            .start_with_clj_on_socket(path)
+            .map(|()| format!("socket {}", path)),
+        [flag, keys_file, ..] if flag == "--tls-keys-file" => server
+            .start_with_clj_tls(keys_file)
+            .map(|port| format!("port {} with TLS", port)),
+        _ => server.start_with_clj().map(|port| format!("port {}", port)),
+    };
+    match started {
+        Ok(address) => {
+            println!("nREPL server started successfully on {}", address);
 
             println!("Server will run for 30 seconds...");
             thread::sleep(Duration::from_secs(30));
@@ -40,9 +90,19 @@ fn start_server() -> io::Result<()> {
     Ok(())
 }
 
-fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
+/// Reads one line from the terminal for code that asks for input.
+fn read_terminal_line() -> Option<String> {
+    io::stdout().flush().ok()?;
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+fn start_client(target: &Target) -> Result<(), Box<dyn std::error::Error>> {
     println!("Connecting to nREPL server...");
-    let mut client = match NreplClient::connect("127.0.0.1", port) {
+    let mut client = match target.connect() {
         Ok(c) => c,
         Err(e) => {
             println!("Failed to connect: {}", e);
@@ -55,21 +115,27 @@ fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
 
     println!("Connected! Setting shorter timeouts for testing...");
     client.set_timeouts(Duration::from_secs(10), Duration::from_secs(5))?;
+    client.set_stdin_provider(read_terminal_line);
+    client.set_interrupt_on_timeout(true);
+    client.set_reconnect_policy(Some(ReconnectPolicy::new().max_attempts(3)));
 
-    // Test describe first
-    // println!("\n=== Testing describe ===");
-    // match client.describe() {
-    //     Ok(desc) => {
-    //         println!("Server description successful");
-    //         if let Some(serde_bencode::value::Value::Dict(ops)) = desc.get("ops") {
-    //             println!("Available operations: {} ops", ops.len());
-    //         }
-    //     }
-    //     Err(e) => {
-    //         println!("Describe failed: {}", e);
-    //         return Ok(());
-    //     }
-    // }
+    println!("\n=== Testing describe ===");
+    match client.describe() {
+        Ok(desc) => {
+            println!("Server description successful");
+            if let Some(nrepl) = &desc.versions.nrepl {
+                println!("nREPL version: {}", nrepl);
+            }
+            if let Some(clojure) = &desc.versions.clojure {
+                println!("Clojure version: {}", clojure);
+            }
+            println!("Available operations: {} ops", desc.ops.len());
+        }
+        Err(e) => {
+            println!("Describe failed: {}", e);
+            return Ok(());
+        }
+    }
 
     // Test session creation
     // println!("\n=== Testing session creation ===");
@@ -81,7 +147,7 @@ fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
     //     }
     // }
 
-    let test_cases = vec![
+    let test_cases = [
         "(+ 1 2 3)",
         "(println \"Hello from Rust!\")",
         "(range 10)",
@@ -89,6 +155,7 @@ fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
         "(str \"Result: \" (+ 10 20 30))",
         "(require '[clojure.string :as str])",
         "(import (java.io File))",
+        "(def x 1) (inc x) (str \"a\" x)",
         "(str \"a\" \"b\")",
     ];
 
@@ -96,30 +163,39 @@ fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
         println!("\n=== Test {} ===", i + 1);
         println!("Evaluating: {}", code);
 
-        match client.eval_with_timeout(code, Duration::from_secs(5)) {
+        // Show printed output as soon as the server sends it
+        let on_event = |event: &EvalEvent| match event {
+            EvalEvent::Out(out) => print!("  | {}", out),
+            EvalEvent::Err(err) => eprint!("  ! {}", err),
+            _ => {}
+        };
+
+        match client.eval_with_callback(code, Duration::from_secs(5), on_event) {
             Ok(result) => {
                 println!("✓ Success!");
-                if let Some(value) = &result.value {
-                    println!("  Value: {}", value);
-                }
-                if !result.output.is_empty() {
-                    println!("  Output: '{}'", result.output);
+                for value in &result.values {
+                    match &value.ns {
+                        Some(ns) => println!("  Value ({}): {}", ns, value.value),
+                        None => println!("  Value: {}", value.value),
+                    }
                 }
                 if result.has_error {
                     println!("  Error: {}", result.error);
                 }
+                if let Some(exception) = &result.exception {
+                    println!("  Exception: {}", exception.class);
+                }
             }
             Err(NreplError::Timeout) => {
-                println!("✗ Timeout - trying to interrupt...");
-                match client.interrupt() {
-                    Ok(_) => println!("  Interrupt sent"),
-                    Err(e) => println!("  Failed to interrupt: {}", e),
-                }
+                println!("✗ Timeout - evaluation interrupted");
             }
             Err(NreplError::ConnectionClosed) => {
                 println!("✗ Connection closed by server");
                 break;
             }
+            Err(NreplError::Reconnected) => {
+                println!("✗ Connection dropped; reconnected with a fresh session");
+            }
             Err(e) => {
                 println!("✗ Error: {}", e);
             }
@@ -141,18 +217,97 @@ fn start_client(port: u16) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn start_repl(target: &Target) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = target.connect()?;
+    client.clone_session()?;
+    client.set_stdin_provider(read_terminal_line);
+
+    let stdin = io::stdin();
+    loop {
+        // Show a user=>-style prompt for the session's current namespace
+        let ns = client.current_ns().unwrap_or_else(|| "user".to_string());
+        print!("{}=> ", ns);
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break;
+        }
+        let code = line.trim();
+        if code.is_empty() {
+            continue;
+        }
+
+        let on_event = |event: &EvalEvent| match event {
+            EvalEvent::Out(out) => print!("{}", out),
+            EvalEvent::Err(err) => eprint!("{}", err),
+            _ => {}
+        };
+
+        match client.eval_with_callback(code, Duration::from_secs(60), on_event) {
+            Ok(result) => {
+                for value in &result.values {
+                    println!("{}", value.value);
+                }
+            }
+            Err(NreplError::ConnectionClosed) => {
+                println!("Connection closed by server");
+                break;
+            }
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Relays WebSocket connections on `<ws-port>` to the nREPL server on `<nrepl-port>`.
+#[cfg(feature = "bridge")]
+fn start_bridge(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    use nrepl_client_server_demo::bridge::WsBridge;
+
+    let [ws_port, nrepl_port, ..] = args else {
+        return Err("usage: bridge <ws-port> <nrepl-port>".into());
+    };
+    let bridge = WsBridge::bind(
+        &format!("127.0.0.1:{}", ws_port),
+        "127.0.0.1",
+        nrepl_port.parse()?,
+    )?;
+    println!(
+        "Relaying ws://{} to nREPL on port {}",
+        bridge.local_addr()?,
+        nrepl_port
+    );
+    bridge.run()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "bridge"))]
+fn start_bridge(_args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    Err("the bridge needs the `bridge` cargo feature".into())
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let codec = take_transport(&mut args);
     let client_or_server = &args[1].clone();
 
     if client_or_server == "server" {
-        start_server();
+        if let Err(e) = start_server(&args[2..], codec) {
+            eprintln!("Server error: {}", e);
+        }
+    } else if client_or_server == "bridge" {
+        if let Err(e) = start_bridge(&args[2..]) {
+            eprintln!("Bridge error: {}", e);
+        }
+    } else if client_or_server == "repl" {
+        if let Err(e) = start_repl(&Target::from_args(&args[2..], codec)) {
+            eprintln!("REPL error: {}", e);
+        }
     } else {
-        let port: u16 = args[2]
-            .clone()
-            .to_string()
-            .parse()
-            .expect("Failed to parse port to u16");
-        start_client(port);
+        if let Err(e) = start_client(&Target::from_args(&args[2..], codec)) {
+            eprintln!("Client error: {}", e);
+        }
     }
 }
@@ -0,0 +1,677 @@
+use crate::bencode::Message;
+use crate::client::NreplError;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_bencode::value::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// An nREPL op and the parameters specific to it.
+///
+/// The `id` and `session` every request may carry, and any parameters the
+/// variant doesn't model, live in the surrounding [`RequestMessage`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Eval {
+        code: String,
+        ns: Option<String>,
+        file: Option<String>,
+        line: Option<i64>,
+        column: Option<i64>,
+    },
+    LoadFile {
+        /// The file's contents.
+        file: String,
+        file_name: Option<String>,
+        file_path: Option<String>,
+    },
+    /// Creates a session, copying the message's session if it names one.
+    Clone,
+    /// Closes the message's session.
+    Close,
+    Describe,
+    LsSessions,
+    Interrupt {
+        interrupt_id: Option<String>,
+    },
+    Stdin {
+        stdin: String,
+    },
+    Complete {
+        prefix: String,
+        ns: Option<String>,
+        context: Option<String>,
+    },
+    Lookup {
+        sym: String,
+        ns: Option<String>,
+    },
+    Stacktrace,
+    /// Any other op, such as one added by middleware. Its parameters are
+    /// kept in the message's extras.
+    Other(String),
+}
+
+impl Request {
+    /// Returns the op name sent on the wire.
+    pub fn op(&self) -> &str {
+        match self {
+            Request::Eval { .. } => "eval",
+            Request::LoadFile { .. } => "load-file",
+            Request::Clone => "clone",
+            Request::Close => "close",
+            Request::Describe => "describe",
+            Request::LsSessions => "ls-sessions",
+            Request::Interrupt { .. } => "interrupt",
+            Request::Stdin { .. } => "stdin",
+            Request::Complete { .. } => "complete",
+            Request::Lookup { .. } => "lookup",
+            Request::Stacktrace => "stacktrace",
+            Request::Other(op) => op,
+        }
+    }
+
+    /// Creates an `eval` of `code` with no other parameters.
+    pub fn eval(code: &str) -> Self {
+        Request::Eval {
+            code: code.to_string(),
+            ns: None,
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+
+    fn write_fields(&self, msg: &mut Message) {
+        msg.insert("op".to_string(), text(self.op()));
+        match self {
+            Request::Eval {
+                code,
+                ns,
+                file,
+                line,
+                column,
+            } => {
+                msg.insert("code".to_string(), text(code));
+                put_str(msg, "ns", ns);
+                put_str(msg, "file", file);
+                put_int(msg, "line", *line);
+                put_int(msg, "column", *column);
+            }
+            Request::LoadFile {
+                file,
+                file_name,
+                file_path,
+            } => {
+                msg.insert("file".to_string(), text(file));
+                put_str(msg, "file-name", file_name);
+                put_str(msg, "file-path", file_path);
+            }
+            Request::Interrupt { interrupt_id } => put_str(msg, "interrupt-id", interrupt_id),
+            Request::Stdin { stdin } => {
+                msg.insert("stdin".to_string(), text(stdin));
+            }
+            Request::Complete {
+                prefix,
+                ns,
+                context,
+            } => {
+                msg.insert("prefix".to_string(), text(prefix));
+                put_str(msg, "ns", ns);
+                put_str(msg, "context", context);
+            }
+            Request::Lookup { sym, ns } => {
+                msg.insert("sym".to_string(), text(sym));
+                put_str(msg, "ns", ns);
+            }
+            Request::Clone
+            | Request::Close
+            | Request::Describe
+            | Request::LsSessions
+            | Request::Stacktrace
+            | Request::Other(_) => {}
+        }
+    }
+
+    /// Reads the op's parameters out of `fields`, leaving everything else.
+    ///
+    /// An op missing a required parameter, or with one of the wrong type,
+    /// becomes `Other` so no field is lost.
+    fn take_fields(op: &str, fields: &mut Message) -> Self {
+        let required = match op {
+            "eval" => "code",
+            "load-file" => "file",
+            "stdin" => "stdin",
+            "complete" => "prefix",
+            "lookup" => "sym",
+            _ => "op",
+        };
+        if required != "op" && !is_str(fields.get(required)) {
+            return Request::Other(op.to_string());
+        }
+
+        match op {
+            "eval" => Request::Eval {
+                code: take_str(fields, "code").unwrap_or_default(),
+                ns: take_str(fields, "ns"),
+                file: take_str(fields, "file"),
+                line: take_int(fields, "line"),
+                column: take_int(fields, "column"),
+            },
+            "load-file" => Request::LoadFile {
+                file: take_str(fields, "file").unwrap_or_default(),
+                file_name: take_str(fields, "file-name"),
+                file_path: take_str(fields, "file-path"),
+            },
+            "clone" => Request::Clone,
+            "close" => Request::Close,
+            "describe" => Request::Describe,
+            "ls-sessions" => Request::LsSessions,
+            "interrupt" => Request::Interrupt {
+                interrupt_id: take_str(fields, "interrupt-id"),
+            },
+            "stdin" => Request::Stdin {
+                stdin: take_str(fields, "stdin").unwrap_or_default(),
+            },
+            "complete" => Request::Complete {
+                prefix: take_str(fields, "prefix").unwrap_or_default(),
+                ns: take_str(fields, "ns"),
+                context: take_str(fields, "context"),
+            },
+            "lookup" => Request::Lookup {
+                sym: take_str(fields, "sym").unwrap_or_default(),
+                ns: take_str(fields, "ns"),
+            },
+            "stacktrace" => Request::Stacktrace,
+            other => Request::Other(other.to_string()),
+        }
+    }
+}
+
+/// A complete request: the op plus the fields shared by every request.
+///
+/// Converting to a [`Message`] and back gives an equal `RequestMessage`, and
+/// converting a message with a string `op` to a `RequestMessage` and back
+/// gives the same message; fields not modelled are kept in `extras`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestMessage {
+    pub request: Request,
+    pub id: Option<String>,
+    pub session: Option<String>,
+    /// Fields the request type doesn't model, such as middleware options.
+    pub extras: BTreeMap<String, Value>,
+}
+
+impl RequestMessage {
+    /// Wraps a request with no id, session or extra fields.
+    pub fn new(request: Request) -> Self {
+        RequestMessage {
+            request,
+            id: None,
+            session: None,
+            extras: BTreeMap::new(),
+        }
+    }
+
+    /// Sends the request in the given session.
+    pub fn in_session(mut self, session: &str) -> Self {
+        self.session = Some(session.to_string());
+        self
+    }
+
+    /// Adds a field the request type doesn't model.
+    pub fn with_extra(mut self, key: &str, value: Value) -> Self {
+        self.extras.insert(key.to_string(), value);
+        self
+    }
+
+    /// Converts the request to the message sent on the wire.
+    pub fn to_message(&self) -> Message {
+        let mut msg: Message = self
+            .extras
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        self.request.write_fields(&mut msg);
+        put_str(&mut msg, "id", &self.id);
+        put_str(&mut msg, "session", &self.session);
+        msg
+    }
+
+    /// Reads a request from a message.
+    ///
+    /// # Returns
+    ///
+    /// Returns the request, or `NreplError::ParseError` if the message has no
+    /// string `op`.
+    pub fn from_message(mut msg: Message) -> Result<Self, NreplError> {
+        let op = take_str(&mut msg, "op")
+            .ok_or_else(|| NreplError::ParseError("request has no op".to_string()))?;
+        let request = Request::take_fields(&op, &mut msg);
+        Ok(RequestMessage {
+            request,
+            id: take_str(&mut msg, "id"),
+            session: take_str(&mut msg, "session"),
+            extras: msg.into_iter().collect(),
+        })
+    }
+}
+
+impl From<Request> for RequestMessage {
+    fn from(request: Request) -> Self {
+        RequestMessage::new(request)
+    }
+}
+
+impl Serialize for RequestMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_message().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for RequestMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let msg = Message::deserialize(deserializer)?;
+        RequestMessage::from_message(msg).map_err(serde::de::Error::custom)
+    }
+}
+
+/// A status flag carried in a response's `status` list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Status {
+    Done,
+    Error,
+    EvalError,
+    NeedInput,
+    Interrupted,
+    SessionIdle,
+    InterruptIdMismatch,
+    UnknownOp,
+    UnknownSession,
+    NamespaceNotFound,
+    /// Any status this type doesn't name.
+    Other(String),
+}
+
+impl Status {
+    /// Returns the status as sent on the wire.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Status::Done => "done",
+            Status::Error => "error",
+            Status::EvalError => "eval-error",
+            Status::NeedInput => "need-input",
+            Status::Interrupted => "interrupted",
+            Status::SessionIdle => "session-idle",
+            Status::InterruptIdMismatch => "interrupt-id-mismatch",
+            Status::UnknownOp => "unknown-op",
+            Status::UnknownSession => "unknown-session",
+            Status::NamespaceNotFound => "namespace-not-found",
+            Status::Other(status) => status,
+        }
+    }
+}
+
+impl From<&str> for Status {
+    fn from(status: &str) -> Self {
+        match status {
+            "done" => Status::Done,
+            "error" => Status::Error,
+            "eval-error" => Status::EvalError,
+            "need-input" => Status::NeedInput,
+            "interrupted" => Status::Interrupted,
+            "session-idle" => Status::SessionIdle,
+            "interrupt-id-mismatch" => Status::InterruptIdMismatch,
+            "unknown-op" => Status::UnknownOp,
+            "unknown-session" => Status::UnknownSession,
+            "namespace-not-found" => Status::NamespaceNotFound,
+            other => Status::Other(other.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Status {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Status {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let status = String::deserialize(deserializer)?;
+        Ok(Status::from(status.as_str()))
+    }
+}
+
+/// One message sent by the server in reply to a request.
+///
+/// Any message can be read as a `Response`; fields that are missing, or
+/// don't have the expected type, are kept in `extras` instead, so converting
+/// back with [`to_message`](Self::to_message) always gives the same message.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    pub id: Option<String>,
+    pub session: Option<String>,
+    pub status: Vec<Status>,
+    pub value: Option<String>,
+    pub ns: Option<String>,
+    pub out: Option<String>,
+    pub err: Option<String>,
+    /// Class of the exception thrown by an eval.
+    pub ex: Option<String>,
+    /// Class of the root cause of `ex`.
+    pub root_ex: Option<String>,
+    /// The session created by `clone`.
+    pub new_session: Option<String>,
+    /// The sessions listed by `ls-sessions`.
+    pub sessions: Option<Vec<String>>,
+    /// Every other field, such as `ops` from `describe` or middleware additions.
+    pub extras: BTreeMap<String, Value>,
+}
+
+impl Response {
+    /// Reads a response from a message.
+    pub fn from_message(mut msg: Message) -> Self {
+        let status = take_str_list(&mut msg, "status")
+            .map(|statuses| statuses.iter().map(|s| Status::from(s.as_str())).collect())
+            .unwrap_or_default();
+        Response {
+            id: take_str(&mut msg, "id"),
+            session: take_str(&mut msg, "session"),
+            status,
+            value: take_str(&mut msg, "value"),
+            ns: take_str(&mut msg, "ns"),
+            out: take_str(&mut msg, "out"),
+            err: take_str(&mut msg, "err"),
+            ex: take_str(&mut msg, "ex"),
+            root_ex: take_str(&mut msg, "root-ex"),
+            new_session: take_str(&mut msg, "new-session"),
+            sessions: take_str_list(&mut msg, "sessions"),
+            extras: msg.into_iter().collect(),
+        }
+    }
+
+    /// Converts the response back to a message.
+    pub fn to_message(&self) -> Message {
+        let mut msg: Message = self
+            .extras
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        put_str(&mut msg, "id", &self.id);
+        put_str(&mut msg, "session", &self.session);
+        if !self.status.is_empty() {
+            let statuses = self.status.iter().map(|s| text(s.as_str())).collect();
+            msg.insert("status".to_string(), Value::List(statuses));
+        }
+        put_str(&mut msg, "value", &self.value);
+        put_str(&mut msg, "ns", &self.ns);
+        put_str(&mut msg, "out", &self.out);
+        put_str(&mut msg, "err", &self.err);
+        put_str(&mut msg, "ex", &self.ex);
+        put_str(&mut msg, "root-ex", &self.root_ex);
+        put_str(&mut msg, "new-session", &self.new_session);
+        if let Some(sessions) = &self.sessions {
+            let sessions = sessions.iter().map(|s| text(s)).collect();
+            msg.insert("sessions".to_string(), Value::List(sessions));
+        }
+        msg
+    }
+
+    /// Returns `true` if the response carries the given status.
+    pub fn has_status(&self, status: &Status) -> bool {
+        self.status.contains(status)
+    }
+
+    /// Returns `true` if this is the last response to its request.
+    pub fn is_done(&self) -> bool {
+        self.has_status(&Status::Done)
+    }
+}
+
+impl From<Message> for Response {
+    fn from(msg: Message) -> Self {
+        Response::from_message(msg)
+    }
+}
+
+impl Serialize for Response {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_message().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Response {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Message::deserialize(deserializer).map(Response::from_message)
+    }
+}
+
+fn text(s: &str) -> Value {
+    Value::Bytes(s.as_bytes().to_vec())
+}
+
+fn put_str(msg: &mut Message, key: &str, value: &Option<String>) {
+    if let Some(value) = value {
+        msg.insert(key.to_string(), text(value));
+    }
+}
+
+fn put_int(msg: &mut Message, key: &str, value: Option<i64>) {
+    if let Some(value) = value {
+        msg.insert(key.to_string(), Value::Int(value));
+    }
+}
+
+fn is_str(value: Option<&Value>) -> bool {
+    matches!(value, Some(Value::Bytes(bytes)) if std::str::from_utf8(bytes).is_ok())
+}
+
+/// Removes a UTF-8 string field; a field of any other type is left in place.
+fn take_str(msg: &mut Message, key: &str) -> Option<String> {
+    if !is_str(msg.get(key)) {
+        return None;
+    }
+    match msg.remove(key) {
+        Some(Value::Bytes(bytes)) => String::from_utf8(bytes).ok(),
+        _ => None,
+    }
+}
+
+/// Removes an integer field; a field of any other type is left in place.
+fn take_int(msg: &mut Message, key: &str) -> Option<i64> {
+    match msg.get(key) {
+        Some(Value::Int(n)) => {
+            let n = *n;
+            msg.remove(key);
+            Some(n)
+        }
+        _ => None,
+    }
+}
+
+/// Removes a list of UTF-8 strings; any other value, or an empty `status`
+/// list that couldn't be told apart from a missing one, is left in place.
+fn take_str_list(msg: &mut Message, key: &str) -> Option<Vec<String>> {
+    match msg.get(key) {
+        Some(Value::List(items))
+            if items.iter().all(|item| is_str(Some(item)))
+                && !(key == "status" && items.is_empty()) => {}
+        _ => return None,
+    }
+    match msg.remove(key) {
+        Some(Value::List(items)) => Some(
+            items
+                .into_iter()
+                .filter_map(|item| match item {
+                    Value::Bytes(bytes) => String::from_utf8(bytes).ok(),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_eval_request_round_trips() {
+        let request = RequestMessage {
+            request: Request::Eval {
+                code: "(+ 1 2)".to_string(),
+                ns: Some("user".to_string()),
+                file: None,
+                line: Some(3),
+                column: None,
+            },
+            id: Some("1".to_string()),
+            session: Some("s".to_string()),
+            extras: BTreeMap::from([("nrepl.middleware.print/print".to_string(), text("pp"))]),
+        };
+        let msg = request.to_message();
+        assert_eq!(msg["op"], text("eval"));
+        assert_eq!(msg["code"], text("(+ 1 2)"));
+        assert_eq!(msg["line"], Value::Int(3));
+        assert_eq!(msg["nrepl.middleware.print/print"], text("pp"));
+        assert!(!msg.contains_key("file"));
+        assert_eq!(RequestMessage::from_message(msg).unwrap(), request);
+
+        let bytes = serde_bencode::to_bytes(&request).unwrap();
+        assert_eq!(
+            serde_bencode::from_bytes::<RequestMessage>(&bytes).unwrap(),
+            request
+        );
+    }
+
+    #[test]
+    fn test_malformed_known_op_is_kept_as_other() {
+        let msg = Message::from([
+            ("op".to_string(), text("eval")),
+            ("code".to_string(), Value::Int(1)),
+        ]);
+        let request = RequestMessage::from_message(msg.clone()).unwrap();
+        assert_eq!(request.request, Request::Other("eval".to_string()));
+        assert_eq!(request.to_message(), msg);
+        assert!(RequestMessage::from_message(Message::new()).is_err());
+    }
+
+    #[test]
+    fn test_response_reads_status_and_keeps_extras() {
+        let msg = Message::from([
+            ("id".to_string(), text("1")),
+            (
+                "status".to_string(),
+                Value::List(vec![text("done"), text("cider/custom")]),
+            ),
+            ("value".to_string(), text("3")),
+            ("ns".to_string(), Value::Int(7)),
+            (
+                "changed-namespaces".to_string(),
+                Value::Dict(Default::default()),
+            ),
+        ]);
+        let response = Response::from_message(msg.clone());
+        assert!(response.is_done());
+        assert_eq!(
+            response.status,
+            vec![Status::Done, Status::Other("cider/custom".to_string())]
+        );
+        assert_eq!(response.value.as_deref(), Some("3"));
+        assert_eq!(response.ns, None);
+        assert_eq!(response.extras["ns"], Value::Int(7));
+        assert!(response.extras.contains_key("changed-namespaces"));
+        assert_eq!(response.to_message(), msg);
+    }
+
+    fn arb_value() -> impl Strategy<Value = Value> {
+        let leaf = prop_oneof![
+            "[a-z0-9 -]{0,8}".prop_map(|s| text(&s)),
+            prop::collection::vec(any::<u8>(), 0..8).prop_map(Value::Bytes),
+            any::<i64>().prop_map(Value::Int),
+        ];
+        leaf.prop_recursive(2, 16, 4, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..4).prop_map(Value::List),
+                prop::collection::hash_map(prop::collection::vec(any::<u8>(), 0..4), inner, 0..4)
+                    .prop_map(Value::Dict),
+            ]
+        })
+    }
+
+    /// Messages mixing the modelled keys with arbitrary ones.
+    fn arb_message() -> impl Strategy<Value = Message> {
+        let key = prop_oneof![
+            prop::sample::select(vec![
+                "id",
+                "session",
+                "status",
+                "value",
+                "ns",
+                "out",
+                "err",
+                "ex",
+                "root-ex",
+                "new-session",
+                "sessions",
+                "code",
+                "file",
+                "line",
+                "column",
+                "file-name",
+                "file-path",
+                "interrupt-id",
+                "stdin",
+                "prefix",
+                "context",
+                "sym",
+            ])
+            .prop_map(str::to_string),
+            "[a-z-]{1,10}",
+        ];
+        let status = prop::collection::vec(
+            prop::sample::select(vec!["done", "error", "need-input", "x-custom"]).prop_map(text),
+            1..3,
+        )
+        .prop_map(Value::List);
+        (
+            prop::collection::hash_map(key, arb_value(), 0..8),
+            prop::option::of(status),
+        )
+            .prop_map(|(mut msg, status)| {
+                if let Some(status) = status {
+                    msg.insert("status".to_string(), status);
+                }
+                msg
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_response_round_trips(msg in arb_message()) {
+            prop_assert_eq!(Response::from_message(msg.clone()).to_message(), msg);
+        }
+
+        #[test]
+        fn prop_request_round_trips(
+            mut msg in arb_message(),
+            op in prop::sample::select(vec![
+                "eval", "load-file", "clone", "close", "describe", "interrupt", "stdin",
+                "complete", "lookup", "ls-sessions", "stacktrace", "cider/format",
+            ]),
+        ) {
+            msg.insert("op".to_string(), text(op));
+            let request = RequestMessage::from_message(msg.clone()).unwrap();
+            prop_assert_eq!(request.request.op(), op);
+            prop_assert_eq!(request.to_message(), msg);
+        }
+    }
+}
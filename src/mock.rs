@@ -0,0 +1,829 @@
+use crate::bencode::{DEFAULT_MAX_MESSAGE_SIZE, Message};
+use crate::client::string_field;
+use crate::codec::Codec;
+use crate::transport::{SharedWriter, Transport};
+use serde_bencode::value::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// One step of a scripted reply.
+#[derive(Clone, Debug)]
+enum Step {
+    /// Send a response message. `id` and `session` are filled in from the request.
+    Send(Message),
+    /// Pause before the next step. Interrupts are honoured while waiting.
+    Delay(Duration),
+    /// Ask for input with `need-input`, then send what arrives as a `value`.
+    ReadInput,
+    /// Shut the connection down.
+    Disconnect,
+}
+
+/// A scripted sequence of responses to a single request.
+///
+/// Each response is sent with the `id` and `session` of the request it answers,
+/// so scripts only need to describe the payload.
+///
+/// ```
+/// use nrepl_client_server_demo::mock::Reply;
+///
+/// let reply = Reply::new().out("hi\n").value("nil").done();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Reply {
+    steps: Vec<Step>,
+}
+
+impl Reply {
+    /// Creates an empty reply that sends nothing.
+    pub fn new() -> Self {
+        Reply { steps: Vec::new() }
+    }
+
+    /// Sends a response with the given fields.
+    pub fn message(mut self, fields: &[(&str, Value)]) -> Self {
+        let message = fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        self.steps.push(Step::Send(message));
+        self
+    }
+
+    /// Sends a response with an `out` field.
+    pub fn out(self, text: &str) -> Self {
+        self.message(&[("out", bytes(text))])
+    }
+
+    /// Sends a response with an `err` field.
+    pub fn err(self, text: &str) -> Self {
+        self.message(&[("err", bytes(text))])
+    }
+
+    /// Sends a response with a `value` field, evaluated in the `user` namespace.
+    pub fn value(self, value: &str) -> Self {
+        self.message(&[("value", bytes(value)), ("ns", bytes("user"))])
+    }
+
+    /// Sends a response with the given `status` list.
+    pub fn status(self, statuses: &[&str]) -> Self {
+        self.message(&[("status", status_list(statuses))])
+    }
+
+    /// Sends the `done` status that completes the request.
+    pub fn done(self) -> Self {
+        self.status(&["done"])
+    }
+
+    /// Waits before sending the next response.
+    pub fn delay(mut self, duration: Duration) -> Self {
+        self.steps.push(Step::Delay(duration));
+        self
+    }
+
+    /// Acts like `(read-line)`: sends `need-input` unless input is already
+    /// buffered, waits for the `stdin` op, and sends the line read as a string
+    /// `value`, or `nil` at end of input.
+    pub fn read_input(mut self) -> Self {
+        self.steps.push(Step::ReadInput);
+        self
+    }
+
+    /// Closes the connection at this point in the script.
+    pub fn disconnect(mut self) -> Self {
+        self.steps.push(Step::Disconnect);
+        self
+    }
+
+    /// Returns `true` if the script waits, so it must not block the connection.
+    fn is_long_running(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|step| matches!(step, Step::Delay(_) | Step::ReadInput))
+    }
+}
+
+/// An eval that is still running its script and can be interrupted.
+struct RunningEval {
+    session: Option<String>,
+    interrupted: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct Shared {
+    eval_replies: Mutex<HashMap<String, Reply>>,
+    op_replies: Mutex<HashMap<String, Reply>>,
+    chunk_size: Mutex<Option<usize>>,
+    sessions: Mutex<HashSet<String>>,
+    running: Mutex<HashMap<String, RunningEval>>,
+    /// Input sent with the `stdin` op, keyed by session.
+    stdin: Mutex<HashMap<String, VecDeque<String>>>,
+    stdin_ready: Condvar,
+    received: Mutex<Vec<Message>>,
+    connections: Mutex<Vec<Box<dyn Transport>>>,
+    stopping: AtomicBool,
+    codec: Codec,
+}
+
+/// A scriptable in-process nREPL server for tests.
+///
+/// `MockServer` speaks bencode (or EDN) over a local TCP port or Unix domain socket and
+/// implements `clone`,
+/// `describe`, `eval`, `load-file`, `lookup`, `ls-sessions`, `stdin`,
+/// `interrupt` and `close` well enough for client tests.
+/// Replies to particular eval forms or ops can be scripted with [`Reply`],
+/// including delays, split packets and disconnects, so every client code path
+/// can be exercised without a JVM.
+pub struct MockServer {
+    port: u16,
+    socket_path: Option<PathBuf>,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    /// Starts a mock server listening on a free port on `127.0.0.1`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the running `MockServer`,
+    /// or an `io::Error` if the listener cannot be bound.
+    pub fn start() -> io::Result<Self> {
+        Self::start_with_codec(Codec::Bencode)
+    }
+
+    /// Starts a mock server scripted with the forms of the standard
+    /// connection test: `(+ 1 1)`, a 100ms `(Thread/sleep 100)`, requiring
+    /// `clojure.string` as `str` and `(str/reverse "abc")`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the running `MockServer`,
+    /// or an `io::Error` if the listener cannot be bound.
+    pub fn start_standard() -> io::Result<Self> {
+        let server = Self::start()?;
+        server.on_eval("(+ 1 1)", Reply::new().value("2").done());
+        server.on_eval(
+            "(Thread/sleep 100)",
+            Reply::new()
+                .delay(Duration::from_millis(100))
+                .value("nil")
+                .done(),
+        );
+        server.on_eval(
+            "(require '[clojure.string :as str])",
+            Reply::new().value("nil").done(),
+        );
+        server.on_eval(
+            "(str/reverse \"abc\")",
+            Reply::new().value("\"cba\"").done(),
+        );
+        Ok(server)
+    }
+
+    /// Starts a mock server on a free port that speaks the given wire format.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The wire format, like nREPL's `--transport`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the running `MockServer`,
+    /// or an `io::Error` if the listener cannot be bound.
+    pub fn start_with_codec(codec: Codec) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let shared = Arc::new(Shared {
+            codec,
+            ..Shared::default()
+        });
+
+        let accept_shared = Arc::clone(&shared);
+        let acceptor = thread::Builder::new()
+            .name("mock-nrepl-accept".to_string())
+            .spawn(move || accept_loop(listener.incoming(), accept_shared))?;
+
+        Ok(MockServer {
+            port,
+            socket_path: None,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Starts a mock server listening on a Unix domain socket.
+    ///
+    /// The socket file is removed when the server is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to create the socket; the file must not exist yet.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the running `MockServer`,
+    /// or an `io::Error` if the socket cannot be bound.
+    #[cfg(unix)]
+    pub fn start_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let listener = UnixListener::bind(path.as_ref())?;
+        let shared = Arc::new(Shared::default());
+
+        let accept_shared = Arc::clone(&shared);
+        let acceptor = thread::Builder::new()
+            .name("mock-nrepl-accept".to_string())
+            .spawn(move || accept_loop(listener.incoming(), accept_shared))?;
+
+        Ok(MockServer {
+            port: 0,
+            socket_path: Some(path.as_ref().to_path_buf()),
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Starts a mock server that speaks TLS on a random local port.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The server certificate, and client verification if clients
+    ///   must present certificates.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the running `MockServer`,
+    /// or an `io::Error` if the listener cannot be bound.
+    #[cfg(feature = "tls")]
+    pub fn start_tls(config: Arc<rustls::ServerConfig>) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let port = listener.local_addr()?.port();
+        let shared = Arc::new(Shared::default());
+
+        let accept_shared = Arc::clone(&shared);
+        let acceptor = thread::Builder::new()
+            .name("mock-nrepl-accept".to_string())
+            .spawn(move || {
+                let incoming = listener
+                    .incoming()
+                    .map(|stream| crate::tls::TlsStream::accept(stream?, Arc::clone(&config)));
+                accept_loop(incoming, accept_shared)
+            })?;
+
+        Ok(MockServer {
+            port,
+            socket_path: None,
+            shared,
+            acceptor: Some(acceptor),
+        })
+    }
+
+    /// Returns the port the mock server is listening on, or 0 for a Unix socket.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Scripts the reply to an `eval` of exactly the given code.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The code string the request must carry.
+    /// * `reply` - The responses to send.
+    pub fn on_eval(&self, code: &str, reply: Reply) {
+        self.shared
+            .eval_replies
+            .lock()
+            .unwrap()
+            .insert(code.to_string(), reply);
+    }
+
+    /// Scripts the reply to every request with the given op, replacing any built-in handling.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The op name, such as `describe`.
+    /// * `reply` - The responses to send.
+    pub fn on_op(&self, op: &str, reply: Reply) {
+        self.shared
+            .op_replies
+            .lock()
+            .unwrap()
+            .insert(op.to_string(), reply);
+    }
+
+    /// Splits every outgoing message into writes of at most `chunk_size` bytes.
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size` - Maximum bytes per write, or `None` to send whole messages.
+    pub fn set_chunk_size(&self, chunk_size: Option<usize>) {
+        *self.shared.chunk_size.lock().unwrap() = chunk_size;
+    }
+
+    /// Returns every request received so far, in arrival order.
+    pub fn received(&self) -> Vec<Message> {
+        self.shared.received.lock().unwrap().clone()
+    }
+
+    /// Returns the most recent request received with the given op.
+    ///
+    /// # Arguments
+    ///
+    /// * `op` - The op name, such as `eval`.
+    pub fn received_op(&self, op: &str) -> Option<Message> {
+        self.received()
+            .into_iter()
+            .rfind(|message| string_field(message, "op").as_deref() == Some(op))
+    }
+
+    /// Returns the ids of the sessions currently open on the server.
+    pub fn sessions(&self) -> Vec<String> {
+        self.shared
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Drops every open client connection.
+    pub fn disconnect_all(&self) {
+        for stream in self.shared.connections.lock().unwrap().drain(..) {
+            let _ = stream.shutdown_transport();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        self.disconnect_all();
+        // Wake the accept loop so it can observe the stop flag
+        match &self.socket_path {
+            #[cfg(unix)]
+            Some(path) => {
+                let _ = UnixStream::connect(path);
+            }
+            _ => {
+                let _ = TcpStream::connect(("127.0.0.1", self.port));
+            }
+        }
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+        if let Some(path) = &self.socket_path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn accept_loop<S, I>(incoming: I, shared: Arc<Shared>)
+where
+    S: Transport,
+    I: Iterator<Item = io::Result<S>>,
+{
+    for stream in incoming {
+        if shared.stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        if let Ok(clone) = stream.try_clone_transport() {
+            shared.connections.lock().unwrap().push(clone);
+        }
+        let conn_shared = Arc::clone(&shared);
+        thread::spawn(move || serve_connection(Box::new(stream), conn_shared));
+    }
+}
+
+fn serve_connection(mut stream: Box<dyn Transport>, shared: Arc<Shared>) {
+    let Ok(writer) = stream.try_clone_transport() else {
+        return;
+    };
+    let writer = Arc::new(Mutex::new(writer));
+    let mut decoder = shared.codec.decoder(DEFAULT_MAX_MESSAGE_SIZE);
+    let mut temp_buffer = [0u8; 4096];
+
+    loop {
+        while let Ok(Some(request)) = decoder.next_message() {
+            shared.received.lock().unwrap().push(request.clone());
+            handle_request(request, &writer, &shared);
+        }
+        match stream.read(&mut temp_buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => decoder.feed(&temp_buffer[..n]),
+        }
+    }
+}
+
+fn handle_request(request: Message, writer: &SharedWriter, shared: &Arc<Shared>) {
+    let op = string_field(&request, "op").unwrap_or_default();
+
+    let scripted = if op == "eval" {
+        string_field(&request, "code")
+            .and_then(|code| shared.eval_replies.lock().unwrap().get(&code).cloned())
+    } else {
+        None
+    };
+    let scripted = scripted.or_else(|| shared.op_replies.lock().unwrap().get(&op).cloned());
+
+    let reply = match scripted {
+        Some(reply) => reply,
+        None => builtin_reply(&op, &request, shared),
+    };
+
+    if op == "eval" && reply.is_long_running() {
+        // Long-running evals run on their own thread so other requests,
+        // including interrupts, are served in the meantime.
+        let interrupted = Arc::new(AtomicBool::new(false));
+        if let Some(id) = string_field(&request, "id") {
+            shared.running.lock().unwrap().insert(
+                id,
+                RunningEval {
+                    session: string_field(&request, "session"),
+                    interrupted: Arc::clone(&interrupted),
+                },
+            );
+        }
+        let writer = Arc::clone(writer);
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            run_script(&reply, &request, &writer, &shared, Some(&interrupted));
+            if let Some(id) = string_field(&request, "id") {
+                shared.running.lock().unwrap().remove(&id);
+            }
+        });
+    } else {
+        run_script(&reply, &request, writer, shared, None);
+    }
+}
+
+/// Produces the reply for ops that have not been scripted.
+fn builtin_reply(op: &str, request: &Message, shared: &Shared) -> Reply {
+    match op {
+        "clone" => {
+            let session = uuid::Uuid::new_v4().to_string();
+            shared.sessions.lock().unwrap().insert(session.clone());
+            Reply::new().message(&[
+                ("new-session", bytes(&session)),
+                ("status", status_list(&["done"])),
+            ])
+        }
+        "describe" => Reply::new().message(&[
+            ("ops", describe_ops()),
+            ("versions", describe_versions()),
+            ("aux", dict(&[("current-ns", bytes("user"))])),
+            ("status", status_list(&["done"])),
+        ]),
+        "eval" | "load-file" => Reply::new().value("nil").done(),
+        "lookup" => Reply::new().message(&[("info", dict(&[]))]).done(),
+        "interrupt" => {
+            let session = string_field(request, "session");
+            let target = string_field(request, "interrupt-id");
+            let running = shared.running.lock().unwrap();
+
+            let mut busy = false;
+            let mut matched = false;
+            for (id, eval) in running.iter().filter(|(_, eval)| eval.session == session) {
+                busy = true;
+                if target.as_ref().is_none_or(|target| target == id) {
+                    eval.interrupted.store(true, Ordering::SeqCst);
+                    matched = true;
+                }
+            }
+
+            if !busy {
+                Reply::new().status(&["done", "session-idle"])
+            } else if matched {
+                Reply::new().done()
+            } else {
+                Reply::new().status(&["done", "error", "interrupt-id-mismatch"])
+            }
+        }
+        "ls-sessions" => {
+            let sessions = shared
+                .sessions
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|session| bytes(session))
+                .collect();
+            Reply::new().message(&[
+                ("sessions", Value::List(sessions)),
+                ("status", status_list(&["done"])),
+            ])
+        }
+        "stdin" => {
+            let session = string_field(request, "session").unwrap_or_default();
+            let input = string_field(request, "stdin").unwrap_or_default();
+            shared
+                .stdin
+                .lock()
+                .unwrap()
+                .entry(session)
+                .or_default()
+                .push_back(input);
+            shared.stdin_ready.notify_all();
+            Reply::new().done()
+        }
+        "close" => {
+            if let Some(session) = string_field(request, "session") {
+                shared.sessions.lock().unwrap().remove(&session);
+            }
+            Reply::new().status(&["done", "session-closed"])
+        }
+        _ => Reply::new().status(&["done", "error", "unknown-op"]),
+    }
+}
+
+fn run_script(
+    reply: &Reply,
+    request: &Message,
+    writer: &SharedWriter,
+    shared: &Shared,
+    interrupted: Option<&Arc<AtomicBool>>,
+) {
+    let is_interrupted = || interrupted.is_some_and(|flag| flag.load(Ordering::SeqCst));
+
+    for step in &reply.steps {
+        if is_interrupted() {
+            let fields =
+                Message::from([("status".to_string(), status_list(&["done", "interrupted"]))]);
+            send(&fields, request, writer, shared);
+            return;
+        }
+        match step {
+            Step::Send(fields) => send(fields, request, writer, shared),
+            Step::Delay(duration) => {
+                let until = Instant::now() + *duration;
+                while Instant::now() < until && !is_interrupted() {
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }
+            Step::ReadInput => {
+                let session = string_field(request, "session").unwrap_or_default();
+                let buffered = shared
+                    .stdin
+                    .lock()
+                    .unwrap()
+                    .get(&session)
+                    .is_some_and(|queue| !queue.is_empty());
+                if !buffered {
+                    let fields =
+                        Message::from([("status".to_string(), status_list(&["need-input"]))]);
+                    send(&fields, request, writer, shared);
+                }
+
+                let Some(input) = wait_for_stdin(&session, shared, &is_interrupted) else {
+                    continue;
+                };
+                let line = input.lines().next().map(|line| format!("{:?}", line));
+                let fields = Message::from([
+                    ("value".to_string(), bytes(line.as_deref().unwrap_or("nil"))),
+                    ("ns".to_string(), bytes("user")),
+                ]);
+                send(&fields, request, writer, shared);
+            }
+            Step::Disconnect => {
+                let _ = writer.lock().unwrap().shutdown_transport();
+                return;
+            }
+        }
+    }
+}
+
+/// Blocks until input arrives for the session, or returns `None` if the
+/// script is interrupted or the server stops first.
+fn wait_for_stdin(
+    session: &str,
+    shared: &Shared,
+    is_interrupted: &dyn Fn() -> bool,
+) -> Option<String> {
+    let mut stdin = shared.stdin.lock().unwrap();
+    loop {
+        if let Some(input) = stdin.get_mut(session).and_then(VecDeque::pop_front) {
+            return Some(input);
+        }
+        if is_interrupted() || shared.stopping.load(Ordering::SeqCst) {
+            return None;
+        }
+        stdin = shared
+            .stdin_ready
+            .wait_timeout(stdin, Duration::from_millis(5))
+            .unwrap()
+            .0;
+    }
+}
+
+fn send(fields: &Message, request: &Message, writer: &SharedWriter, shared: &Shared) {
+    let mut message = fields.clone();
+    for key in ["id", "session"] {
+        if let Some(value) = request.get(key) {
+            message
+                .entry(key.to_string())
+                .or_insert_with(|| value.clone());
+        }
+    }
+    let Ok(encoded) = shared.codec.encode(&message) else {
+        return;
+    };
+
+    let chunk_size = *shared.chunk_size.lock().unwrap();
+    let mut stream = writer.lock().unwrap();
+    match chunk_size {
+        Some(size) if size > 0 => {
+            for chunk in encoded.chunks(size) {
+                if stream
+                    .write_all(chunk)
+                    .and_then(|_| stream.flush())
+                    .is_err()
+                {
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        _ => {
+            let _ = stream.write_all(&encoded);
+        }
+    }
+}
+
+fn bytes(s: &str) -> Value {
+    Value::Bytes(s.as_bytes().to_vec())
+}
+
+fn status_list(statuses: &[&str]) -> Value {
+    Value::List(statuses.iter().map(|s| bytes(s)).collect())
+}
+
+fn dict(entries: &[(&str, Value)]) -> Value {
+    Value::Dict(
+        entries
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.clone()))
+            .collect(),
+    )
+}
+
+fn describe_ops() -> Value {
+    let ops = [
+        "clone",
+        "close",
+        "describe",
+        "eval",
+        "interrupt",
+        "load-file",
+        "lookup",
+        "ls-sessions",
+        "stdin",
+    ];
+    dict(
+        &ops.iter()
+            .map(|op| (*op, Value::Dict(HashMap::new())))
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn describe_versions() -> Value {
+    let version = |major: i64, minor: i64, incremental: i64| {
+        dict(&[
+            ("major", Value::Int(major)),
+            ("minor", Value::Int(minor)),
+            ("incremental", Value::Int(incremental)),
+            (
+                "version-string",
+                bytes(&format!("{}.{}.{}", major, minor, incremental)),
+            ),
+        ])
+    };
+    dict(&[
+        ("nrepl", version(1, 3, 1)),
+        ("clojure", version(1, 12, 0)),
+        ("java", version(21, 0, 1)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::BencodeDecoder;
+
+    /// A raw connection to the mock that counts the reads it takes.
+    struct RawClient {
+        stream: TcpStream,
+        decoder: BencodeDecoder,
+        reads: usize,
+    }
+
+    impl RawClient {
+        fn connect(server: &MockServer) -> RawClient {
+            let stream = TcpStream::connect(("127.0.0.1", server.port())).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            RawClient {
+                stream,
+                decoder: BencodeDecoder::new(),
+                reads: 0,
+            }
+        }
+
+        fn eval(&mut self, code: &str) {
+            let request = Message::from([
+                ("op".to_string(), bytes("eval")),
+                ("code".to_string(), bytes(code)),
+                ("id".to_string(), bytes("1")),
+            ]);
+            let encoded = serde_bencode::to_bytes(&request).unwrap();
+            self.stream.write_all(&encoded).unwrap();
+        }
+
+        /// Returns the next message, or `None` once the server hangs up.
+        fn next_message(&mut self) -> Option<Message> {
+            let mut buffer = [0u8; 4096];
+            loop {
+                if let Some(message) = self.decoder.next_message().unwrap() {
+                    return Some(message);
+                }
+                match self.stream.read(&mut buffer).unwrap() {
+                    0 => return None,
+                    n => {
+                        self.reads += 1;
+                        self.decoder.feed(&buffer[..n]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_size_splits_replies() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(str)", Reply::new().value("a-fairly-long-value").done());
+        server.set_chunk_size(Some(3));
+        let mut client = RawClient::connect(&server);
+
+        client.eval("(str)");
+        let reply = client.next_message().unwrap();
+        assert_eq!(
+            string_field(&reply, "value").as_deref(),
+            Some("a-fairly-long-value")
+        );
+        assert_eq!(string_field(&reply, "id").as_deref(), Some("1"));
+        assert!(client.reads > 1);
+    }
+
+    #[test]
+    fn test_delay_pauses_between_replies() {
+        let server = MockServer::start().unwrap();
+        server.on_eval(
+            "(slow)",
+            Reply::new()
+                .out("first")
+                .delay(Duration::from_millis(150))
+                .value("nil")
+                .done(),
+        );
+        let mut client = RawClient::connect(&server);
+
+        client.eval("(slow)");
+        let first = client.next_message().unwrap();
+        assert_eq!(string_field(&first, "out").as_deref(), Some("first"));
+        let started = Instant::now();
+        let second = client.next_message().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(140));
+        assert_eq!(string_field(&second, "value").as_deref(), Some("nil"));
+    }
+
+    #[test]
+    fn test_disconnect_closes_the_connection() {
+        let server = MockServer::start().unwrap();
+        server.on_eval("(bye)", Reply::new().out("bye").disconnect().done());
+        let mut client = RawClient::connect(&server);
+
+        client.eval("(bye)");
+        let reply = client.next_message().unwrap();
+        assert_eq!(string_field(&reply, "out").as_deref(), Some("bye"));
+        // The steps after the disconnect are never sent
+        assert!(client.next_message().is_none());
+        assert_eq!(
+            server.received_op("eval").map(|m| m["code"].clone()),
+            Some(bytes("(bye)"))
+        );
+
+        // The server keeps accepting new connections
+        let mut other = RawClient::connect(&server);
+        other.eval("(+ 1 1)");
+        assert!(other.next_message().is_some());
+    }
+}
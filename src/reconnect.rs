@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// How `NreplClient` re-establishes a dropped connection.
+///
+/// Reconnecting is opt-in; set a policy with
+/// [`NreplClient::set_reconnect_policy`](crate::client::NreplClient::set_reconnect_policy).
+/// The first attempt is made straight away and later attempts wait an
+/// exponentially growing delay, capped at `max_backoff`.
+///
+/// Server-side session state does not survive a reconnect. The client opens
+/// fresh sessions in place of the ones it had and replays `init_script` in each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Connection attempts before giving up; at least one is always made.
+    pub max_attempts: u32,
+    /// Delay before the second attempt.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_backoff: Duration,
+    /// Code evaluated in every recreated session, such as `require`s.
+    pub init_script: Option<String>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            init_script: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Creates a policy with 5 attempts and backoff from 100ms up to 5s.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of connection attempts.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Sets the first delay between attempts and the largest it may grow to.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Sets code to evaluate in each session recreated after reconnecting.
+    pub fn init_script(mut self, code: &str) -> Self {
+        self.init_script = Some(code.to_string());
+        self
+    }
+
+    /// Returns how long to wait before the given attempt, counting from zero.
+    pub(crate) fn delay_before(&self, attempt: u32) -> Duration {
+        if attempt == 0 {
+            return Duration::ZERO;
+        }
+        let factor = 2u32.saturating_pow(attempt - 1);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy =
+            ReconnectPolicy::new().backoff(Duration::from_millis(100), Duration::from_millis(500));
+        let delays: Vec<u128> = (0..6)
+            .map(|attempt| policy.delay_before(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, vec![0, 100, 200, 400, 500, 500]);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::bencode::Message;
+use crate::client::{NreplError, has_status, string_field};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A response routed to a request, or the error that ended the connection.
+pub(crate) type Routed = Result<Message, NreplError>;
+
+/// The sending half of the channel a request's responses are routed to, so
+/// the blocking and async clients can share a [`Router`].
+pub(crate) trait RouteSender: Sized {
+    type Receiver;
+
+    /// Creates a channel for one request.
+    fn channel() -> (Self, Self::Receiver);
+
+    /// Sends a response, returning `false` if the receiver is gone.
+    fn deliver(&self, response: Routed) -> bool;
+}
+
+impl RouteSender for std::sync::mpsc::Sender<Routed> {
+    type Receiver = std::sync::mpsc::Receiver<Routed>;
+
+    fn channel() -> (Self, Self::Receiver) {
+        std::sync::mpsc::channel()
+    }
+
+    fn deliver(&self, response: Routed) -> bool {
+        self.send(response).is_ok()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl RouteSender for tokio::sync::mpsc::UnboundedSender<Routed> {
+    type Receiver = tokio::sync::mpsc::UnboundedReceiver<Routed>;
+
+    fn channel() -> (Self, Self::Receiver) {
+        tokio::sync::mpsc::unbounded_channel()
+    }
+
+    fn deliver(&self, response: Routed) -> bool {
+        self.send(response).is_ok()
+    }
+}
+
+/// Routing table shared between a client and its reader.
+///
+/// Each in-flight request registers a channel under its message `id`; the
+/// reader forwards every response to the channel with the matching id.
+pub(crate) struct Router<S> {
+    routes: Mutex<HashMap<String, S>>,
+    closed: AtomicBool,
+}
+
+impl<S: RouteSender> Router<S> {
+    pub(crate) fn new() -> Self {
+        Router {
+            routes: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn register(&self, id: &str) -> Result<S::Receiver, NreplError> {
+        let mut routes = self.routes.lock().unwrap();
+        // Checked under the lock so a route cannot be added after `fail_all` ran
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(NreplError::ConnectionClosed);
+        }
+        let (sender, receiver) = S::channel();
+        routes.insert(id.to_string(), sender);
+        Ok(receiver)
+    }
+
+    pub(crate) fn unregister(&self, id: &str) {
+        self.routes.lock().unwrap().remove(id);
+    }
+
+    pub(crate) fn dispatch(&self, message: Message) {
+        // Messages without an id, or for a request nobody is waiting on
+        // any more, have no one to deliver to and are dropped.
+        let Some(id) = string_field(&message, "id") else {
+            return;
+        };
+        let done = has_status(&message, "done");
+
+        let mut routes = self.routes.lock().unwrap();
+        if let Some(sender) = routes.get(&id) {
+            let delivered = sender.deliver(Ok(message));
+            if done || !delivered {
+                routes.remove(&id);
+            }
+        }
+    }
+
+    /// Ends every waiting request with `error` and refuses new ones.
+    pub(crate) fn fail_all(&self, error: &NreplError) {
+        let mut routes = self.routes.lock().unwrap();
+        self.closed.store(true, Ordering::SeqCst);
+        for (_, sender) in routes.drain() {
+            let _ = sender.deliver(Err(duplicate_error(error)));
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// A request's place in a [`Router`], tracking whether it has completed.
+///
+/// Dropping it before the `done` response stops routing further responses.
+pub(crate) struct Route<S: RouteSender> {
+    id: String,
+    router: Arc<Router<S>>,
+    done: bool,
+}
+
+impl<S: RouteSender> Route<S> {
+    /// Registers `id` with the router.
+    ///
+    /// # Returns
+    ///
+    /// Returns the route and the receiver its responses arrive on, or
+    /// `NreplError::ConnectionClosed` if the router has failed.
+    pub(crate) fn register(
+        router: &Arc<Router<S>>,
+        id: String,
+    ) -> Result<(Self, S::Receiver), NreplError> {
+        let receiver = router.register(&id)?;
+        let route = Route {
+            id,
+            router: Arc::clone(router),
+            done: false,
+        };
+        Ok((route, receiver))
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Fails once the `done` response has been received, since nothing more
+    /// will arrive.
+    pub(crate) fn ensure_not_done(&self) -> Result<(), NreplError> {
+        if self.done {
+            return Err(NreplError::Other(format!(
+                "Request {} has already completed",
+                self.id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Unwraps a received response, noting whether it completes the request.
+    pub(crate) fn observe(&mut self, response: Routed) -> Result<Message, NreplError> {
+        let message = response?;
+        if has_status(&message, "done") {
+            self.done = true;
+        }
+        Ok(message)
+    }
+}
+
+impl<S: RouteSender> Drop for Route<S> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.router.unregister(&self.id);
+        }
+    }
+}
+
+/// Produces an equivalent error for each waiting request, since `NreplError`
+/// is not `Clone`.
+pub(crate) fn duplicate_error(error: &NreplError) -> NreplError {
+    match error {
+        NreplError::ConnectionClosed => NreplError::ConnectionClosed,
+        NreplError::Timeout => NreplError::Timeout,
+        NreplError::ParseError(msg) => NreplError::ParseError(msg.clone()),
+        NreplError::IoError(e) => NreplError::IoError(std::io::Error::new(e.kind(), e.to_string())),
+        NreplError::LimitExceeded { setting, limit } => NreplError::LimitExceeded {
+            setting,
+            limit: limit.clone(),
+        },
+        NreplError::Reconnected => NreplError::Reconnected,
+        NreplError::UnsupportedOp(op) => NreplError::UnsupportedOp(op.clone()),
+        NreplError::Other(msg) => NreplError::Other(msg.clone()),
+    }
+}
@@ -1,9 +1,23 @@
+use crate::codec::Codec;
 use regex::Regex;
+use std::ffi::OsStr;
 use std::io;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, Stdio};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+const CLJ_ARGS: [&str; 5] = [
+    "-Sdeps",
+    "{:deps {nrepl/nrepl {:mvn/version \"1.3.1\"}}}",
+    "-M",
+    "-m",
+    "nrepl.cmdline",
+];
+
+/// How long to wait for a socket-bound server to create its socket file.
+const SOCKET_STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
 
 /// A server manager for launching and controlling an nREPL server process.
 ///
@@ -12,6 +26,14 @@ use std::time::Duration;
 pub struct NreplServer {
     child: Option<Child>,
     port: Option<u16>,
+    socket_path: Option<PathBuf>,
+    codec: Codec,
+}
+
+impl Default for NreplServer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NreplServer {
@@ -24,6 +46,31 @@ impl NreplServer {
         Self {
             child: None,
             port: None,
+            socket_path: None,
+            codec: Codec::Bencode,
+        }
+    }
+
+    /// Sets the wire format servers started with the Clojure CLI use, passed
+    /// as `--transport`. Clients must connect with the same codec.
+    ///
+    /// # Arguments
+    ///
+    /// * `codec` - The wire format; bencode is nREPL's default.
+    pub fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    /// Returns the wire format the server speaks.
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the `--transport` arguments for the configured codec, if any.
+    fn transport_args(&self) -> Vec<&'static str> {
+        match self.codec {
+            Codec::Bencode => Vec::new(),
+            codec => vec!["--transport", codec.transport_var()],
         }
     }
 
@@ -40,18 +87,34 @@ impl NreplServer {
     /// Returns a `Result` containing the port number the server is listening on if successful,
     /// or an `io::Error` if the server fails to start.
     pub fn start_with_clj(&mut self) -> io::Result<u16> {
-        let mut cmd = Command::new("clj");
+        self.start_clj_on_port(&[])
+    }
 
-        let args = [
-            "-Sdeps",
-            "{:deps {nrepl/nrepl {:mvn/version \"1.3.1\"}}}",
-            "-M",
-            "-m",
-            "nrepl.cmdline",
-        ];
+    /// Starts a TLS-enabled nREPL server using the Clojure CLI.
+    ///
+    /// The server only accepts clients presenting a certificate signed by the
+    /// CA in the keys file; connect with `NreplClient::connect_tls`.
+    ///
+    /// # Arguments
+    ///
+    /// * `keys_file` - PEM file holding the CA certificate, the server
+    ///   certificate and the server's private key, passed as `--tls-keys-file`.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the port number the server is listening on if successful,
+    /// or an `io::Error` if the server fails to start.
+    pub fn start_with_clj_tls<P: AsRef<Path>>(&mut self, keys_file: P) -> io::Result<u16> {
+        self.start_clj_on_port(&["--tls-keys-file".as_ref(), keys_file.as_ref().as_os_str()])
+    }
+
+    fn start_clj_on_port(&mut self, extra_args: &[&OsStr]) -> io::Result<u16> {
+        let mut cmd = Command::new("clj");
 
         let mut child = cmd
-            .args(&args)
+            .args(CLJ_ARGS)
+            .args(self.transport_args())
+            .args(extra_args)
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit())
             .spawn()?;
@@ -63,11 +126,11 @@ impl NreplServer {
 
             // Give it some time to start and read a few lines
             for _ in 0..10 {
-                if let Some(Ok(line)) = lines_iter.next() {
-                    if let Some(port) = self.parse_port_from_output(&line) {
-                        confirmed_port = port;
-                        break;
-                    }
+                if let Some(Ok(line)) = lines_iter.next()
+                    && let Some(port) = self.parse_port_from_output(&line)
+                {
+                    confirmed_port = port;
+                    break;
                 }
                 thread::sleep(Duration::from_millis(200));
             }
@@ -79,6 +142,64 @@ impl NreplServer {
         Ok(confirmed_port)
     }
 
+    /// Starts an nREPL server using the Clojure CLI, listening on a Unix domain
+    /// socket instead of a TCP port.
+    ///
+    /// Waits until the server accepts connections on the socket. An existing
+    /// file at `path` is refused rather than removed, since it may belong to
+    /// another server.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the socket file the server should create.
+    ///
+    /// # Returns
+    ///
+    /// Returns `Ok(())` once the socket is ready, or an `io::Error` if `path`
+    /// already exists, the server exits, or the socket doesn't accept
+    /// connections within 60 seconds.
+    pub fn start_with_clj_on_socket<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        let child = Command::new("clj")
+            .args(CLJ_ARGS)
+            .args(self.transport_args())
+            .arg("--socket")
+            .arg(path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        self.child = Some(child);
+
+        // The socket is only recorded, and so removed by `stop`, once this
+        // server is known to be listening on it
+        let deadline = Instant::now() + SOCKET_STARTUP_TIMEOUT;
+        while !socket_accepts(path) {
+            if !self.is_running() {
+                self.child = None;
+                return Err(io::Error::other(
+                    "nREPL server exited before creating its socket",
+                ));
+            }
+            if Instant::now() >= deadline {
+                self.stop()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "nREPL server did not listen on its socket in time",
+                ));
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        self.socket_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
     /// Starts an nREPL server using Leiningen (`lein repl :headless`).
     ///
     /// # Returns
@@ -87,7 +208,7 @@ impl NreplServer {
     /// or an `io::Error` if the server fails to start.
     pub fn start_with_lein(&mut self) -> io::Result<u16> {
         let mut cmd = Command::new("lein");
-        cmd.args(&["repl", ":headless"])
+        cmd.args(["repl", ":headless"])
             .stdout(Stdio::piped())
             .stderr(Stdio::piped());
 
@@ -100,11 +221,11 @@ impl NreplServer {
 
             // Give it some time to start and read a few lines
             for _ in 0..10 {
-                if let Some(Ok(line)) = lines_iter.next() {
-                    if let Some(port) = self.parse_port_from_output(&line) {
-                        confirmed_port = port;
-                        break;
-                    }
+                if let Some(Ok(line)) = lines_iter.next()
+                    && let Some(port) = self.parse_port_from_output(&line)
+                {
+                    confirmed_port = port;
+                    break;
                 }
                 thread::sleep(Duration::from_millis(200));
             }
@@ -139,6 +260,12 @@ impl NreplServer {
         self.port
     }
 
+    /// Returns the Unix domain socket the nREPL server is listening on, if it
+    /// was started with [`start_with_clj_on_socket`](Self::start_with_clj_on_socket).
+    pub fn socket_path(&self) -> Option<&Path> {
+        self.socket_path.as_deref()
+    }
+
     /// Reads and collects output lines from the server process's stdout.
     ///
     /// # Returns
@@ -148,14 +275,14 @@ impl NreplServer {
     pub fn read_output(&mut self) -> io::Result<Vec<String>> {
         let mut lines = Vec::new();
 
-        if let Some(ref mut child) = self.child {
-            if let Some(stdout) = child.stdout.take() {
-                let reader = BufReader::new(stdout);
-                for line in reader.lines() {
-                    match line {
-                        Ok(l) => lines.push(l),
-                        Err(_) => break,
-                    }
+        if let Some(ref mut child) = self.child
+            && let Some(stdout) = child.stdout.take()
+        {
+            let reader = BufReader::new(stdout);
+            for line in reader.lines() {
+                match line {
+                    Ok(l) => lines.push(l),
+                    Err(_) => break,
                 }
             }
         }
@@ -163,7 +290,8 @@ impl NreplServer {
         Ok(lines)
     }
 
-    /// Stops the nREPL server process if it is running.
+    /// Stops the nREPL server process if it is running, removing its socket
+    /// file if it had one.
     ///
     /// # Returns
     ///
@@ -174,10 +302,25 @@ impl NreplServer {
             child.kill()?;
             child.wait()?;
         }
+        if let Some(path) = self.socket_path.take() {
+            // A killed JVM doesn't get to delete its socket file
+            let _ = std::fs::remove_file(path);
+        }
         Ok(())
     }
 }
 
+/// Whether a server is listening on the Unix domain socket at `path`.
+#[cfg(unix)]
+fn socket_accepts(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+fn socket_accepts(path: &Path) -> bool {
+    path.exists()
+}
+
 impl Drop for NreplServer {
     fn drop(&mut self) {
         let _ = self.stop();
@@ -193,6 +336,8 @@ mod tests {
         let mut server = NreplServer::new();
         assert!(!&server.is_running());
         assert_eq!(server.port(), None);
+        assert_eq!(server.socket_path(), None);
+        assert_eq!(server.codec(), Codec::Bencode);
     }
 
     #[test]
@@ -206,6 +351,21 @@ mod tests {
     }
 
     #[test]
+    fn test_socket_start_refuses_an_existing_path() {
+        let path = std::env::temp_dir().join(format!("nrepl-stale-{}.sock", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+
+        let mut server = NreplServer::new();
+        let err = server.start_with_clj_on_socket(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(!server.is_running());
+        assert_eq!(server.socket_path(), None);
+
+        server.stop().unwrap();
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
     /* fn test_find_available_port() {
         let port = NreplServer::find_available_port();
         assert!(port.is_ok());
@@ -0,0 +1,473 @@
+use crate::transport::Transport;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore};
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Certificates for connecting to an nREPL server over TLS.
+///
+/// nREPL's TLS servers (`--tls-keys-file`) only accept clients that present a
+/// certificate signed by the server's CA, so a working config usually has both
+/// a CA and a client certificate. [`TlsConfig::from_keys_file`] reads the same
+/// keys file layout nREPL uses.
+///
+/// ```no_run
+/// use nrepl_client_server_demo::client::NreplClient;
+/// use nrepl_client_server_demo::tls::TlsConfig;
+///
+/// let tls = TlsConfig::new()
+///     .add_ca_file("ca.pem")?
+///     .client_cert_files("client.pem", "client.key")?;
+/// let client = NreplClient::connect_tls("staging.example.com", 7888, tls);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TlsConfig {
+    roots: Vec<CertificateDer<'static>>,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    server_name: Option<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for TlsConfig {
+    fn clone(&self) -> Self {
+        TlsConfig {
+            roots: self.roots.clone(),
+            client_cert: self
+                .client_cert
+                .as_ref()
+                .map(|(chain, key)| (chain.clone(), key.clone_key())),
+            server_name: self.server_name.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("roots", &self.roots.len())
+            .field("client_cert", &self.client_cert.is_some())
+            .field("server_name", &self.server_name)
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Creates a config that trusts no CAs and presents no client certificate.
+    pub fn new() -> Self {
+        TlsConfig {
+            roots: Vec::new(),
+            client_cert: None,
+            server_name: None,
+        }
+    }
+
+    /// Reads an nREPL keys file: the CA certificate, then the client
+    /// certificate chain, then the client's private key, all PEM encoded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The keys file, in the format nREPL's `--tls-keys-file` takes.
+    ///
+    /// # Returns
+    ///
+    /// Returns a `Result` containing the config, or an `io::Error` if the file
+    /// can't be read or lacks a CA, a client certificate or a key.
+    pub fn from_keys_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let pem = std::fs::read(path)?;
+        let mut certs = parse_certs(&pem)?.into_iter();
+        let ca = certs
+            .next()
+            .ok_or_else(|| invalid("keys file contains no CA certificate"))?;
+        let chain: Vec<_> = certs.collect();
+        if chain.is_empty() {
+            return Err(invalid("keys file contains no client certificate"));
+        }
+        let key = parse_key(&pem)?;
+
+        Ok(TlsConfig {
+            roots: vec![ca],
+            client_cert: Some((chain, key)),
+            server_name: None,
+        })
+    }
+
+    /// Trusts the PEM-encoded CA certificates in `pem`.
+    pub fn add_ca_pem(mut self, pem: &[u8]) -> io::Result<Self> {
+        let certs = parse_certs(pem)?;
+        if certs.is_empty() {
+            return Err(invalid("no CA certificates found"));
+        }
+        self.roots.extend(certs);
+        Ok(self)
+    }
+
+    /// Trusts the PEM-encoded CA certificates in the file at `path`.
+    pub fn add_ca_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self> {
+        let pem = std::fs::read(path)?;
+        self.add_ca_pem(&pem)
+    }
+
+    /// Presents a client certificate, for servers that require one.
+    ///
+    /// # Arguments
+    ///
+    /// * `cert_pem` - The client certificate, optionally followed by intermediates.
+    /// * `key_pem` - The certificate's private key.
+    pub fn client_cert_pem(mut self, cert_pem: &[u8], key_pem: &[u8]) -> io::Result<Self> {
+        let chain = parse_certs(cert_pem)?;
+        if chain.is_empty() {
+            return Err(invalid("no client certificate found"));
+        }
+        self.client_cert = Some((chain, parse_key(key_pem)?));
+        Ok(self)
+    }
+
+    /// Presents the client certificate and key read from PEM files.
+    pub fn client_cert_files<P: AsRef<Path>, Q: AsRef<Path>>(
+        self,
+        cert_path: P,
+        key_path: Q,
+    ) -> io::Result<Self> {
+        let cert_pem = std::fs::read(cert_path)?;
+        let key_pem = std::fs::read(key_path)?;
+        self.client_cert_pem(&cert_pem, &key_pem)
+    }
+
+    /// Verifies the server certificate against `name` instead of the host
+    /// passed to `connect`, for example when connecting through a tunnel.
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Returns the name the server certificate must be valid for.
+    pub(crate) fn server_name_for(&self, host: &str) -> io::Result<ServerName<'static>> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        ServerName::try_from(name.to_string())
+            .map_err(|_| invalid(&format!("invalid TLS server name '{}'", name)))
+    }
+
+    /// Builds the rustls client configuration.
+    pub(crate) fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let mut roots = RootCertStore::empty();
+        for cert in &self.roots {
+            roots
+                .add(cert.clone())
+                .map_err(|e| invalid(&format!("invalid CA certificate: {}", e)))?;
+        }
+
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(io::Error::other)?
+                .with_root_certificates(roots);
+        let config = match &self.client_cert {
+            Some((chain, key)) => builder
+                .with_client_auth_cert(chain.clone(), key.clone_key())
+                .map_err(|e| invalid(&format!("invalid client certificate: {}", e)))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message.to_string())
+}
+
+fn parse_certs(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<_, _>>()
+        .map_err(|e| invalid(&format!("invalid certificate PEM: {}", e)))
+}
+
+fn parse_key(pem: &[u8]) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_slice(pem)
+        .map_err(|e| invalid(&format!("invalid private key PEM: {}", e)))
+}
+
+/// A TLS session over TCP that can be shared between a reader and writers.
+///
+/// The rustls state sits behind a mutex shared by every clone, while each clone
+/// owns its own handle to the socket. Reads from the socket happen outside the
+/// lock, so a reader blocked waiting for data doesn't hold up writers.
+pub(crate) struct TlsStream {
+    conn: Arc<Mutex<Connection>>,
+    socket: TcpStream,
+}
+
+impl TlsStream {
+    /// Wraps a connected socket as the client side and completes the handshake.
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - The connected TCP stream.
+    /// * `server_name` - The name the server certificate must be valid for.
+    /// * `config` - The client configuration.
+    /// * `timeout` - How long the handshake may wait for the server.
+    pub(crate) fn connect(
+        mut socket: TcpStream,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let mut conn = ClientConnection::new(config, server_name).map_err(io::Error::other)?;
+
+        // Handshake up front so certificate problems surface from connect
+        socket.set_read_timeout(Some(timeout))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut socket)?;
+        }
+        socket.set_read_timeout(None)?;
+
+        Ok(TlsStream {
+            conn: Arc::new(Mutex::new(conn.into())),
+            socket,
+        })
+    }
+
+    /// Wraps an accepted socket as the server side. The handshake proceeds as
+    /// the stream is read. Only the mock server accepts TLS connections.
+    #[cfg(any(test, feature = "mock"))]
+    pub(crate) fn accept(socket: TcpStream, config: Arc<rustls::ServerConfig>) -> io::Result<Self> {
+        let conn = rustls::ServerConnection::new(config).map_err(io::Error::other)?;
+        Ok(TlsStream {
+            conn: Arc::new(Mutex::new(conn.into())),
+            socket,
+        })
+    }
+
+    /// Sends whatever TLS records rustls has queued.
+    fn flush_tls(conn: &mut Connection, mut socket: &TcpStream) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut socket)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = [0u8; 4096];
+        loop {
+            {
+                let mut conn = self.conn.lock().unwrap();
+                match conn.reader().read(buf) {
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            // Only read more once the plaintext is drained, which keeps rustls'
+            // receive buffer from filling up
+            let n = (&self.socket).read(&mut incoming)?;
+            let mut conn = self.conn.lock().unwrap();
+            if n == 0 {
+                // Closed without close_notify; rustls reports that as an error
+                return Err(ErrorKind::UnexpectedEof.into());
+            }
+            let mut records = &incoming[..n];
+            while !records.is_empty() {
+                conn.read_tls(&mut records)?;
+                conn.process_new_packets()
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+            // Handshake replies and alerts
+            Self::flush_tls(&mut conn, &self.socket)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let n = conn.writer().write(buf)?;
+        Self::flush_tls(&mut conn, &self.socket)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        conn.writer().flush()?;
+        Self::flush_tls(&mut conn, &self.socket)
+    }
+}
+
+impl Transport for TlsStream {
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(TlsStream {
+            conn: Arc::clone(&self.conn),
+            socket: self.socket.try_clone()?,
+        }))
+    }
+
+    fn shutdown_transport(&self) -> io::Result<()> {
+        // Say goodbye if no one else is mid-write; the shutdown happens regardless
+        if let Ok(mut conn) = self.conn.try_lock() {
+            conn.send_close_notify();
+            let _ = Self::flush_tls(&mut conn, &self.socket);
+        }
+        self.socket.shutdown(Shutdown::Both)
+    }
+
+    fn set_transport_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{NreplClient, NreplError};
+    use crate::mock::{MockServer, Reply};
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use rustls::ServerConfig;
+    use rustls::server::WebPkiClientVerifier;
+
+    /// A CA with a server certificate for 127.0.0.1 and a client certificate,
+    /// each as (certificate PEM, key PEM).
+    struct TestPki {
+        ca_pem: String,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    fn generate_pki() -> TestPki {
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+
+        let issue = |names: &[&str]| {
+            let key = KeyPair::generate().unwrap();
+            let params =
+                CertificateParams::new(names.iter().map(|n| n.to_string()).collect::<Vec<_>>())
+                    .unwrap();
+            let cert = params.signed_by(&key, &ca).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+
+        TestPki {
+            ca_pem: ca.pem(),
+            server: issue(&["127.0.0.1", "localhost"]),
+            client: issue(&["nrepl-client"]),
+        }
+    }
+
+    /// A mock server that, like nREPL, requires client certificates.
+    fn start_server(pki: &TestPki) -> MockServer {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        roots.add_parsable_certificates(parse_certs(pki.ca_pem.as_bytes()).unwrap());
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone())
+            .build()
+            .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                parse_certs(pki.server.0.as_bytes()).unwrap(),
+                parse_key(pki.server.1.as_bytes()).unwrap(),
+            )
+            .unwrap();
+        MockServer::start_tls(Arc::new(config)).unwrap()
+    }
+
+    #[test]
+    fn test_eval_over_tls_with_client_certificate() {
+        let pki = generate_pki();
+        let server = start_server(&pki);
+        server.on_eval("(+ 1 2)", Reply::new().value("3").done());
+
+        let tls = TlsConfig::new()
+            .add_ca_pem(pki.ca_pem.as_bytes())
+            .unwrap()
+            .client_cert_pem(pki.client.0.as_bytes(), pki.client.1.as_bytes())
+            .unwrap();
+        let mut client = NreplClient::connect_tls("127.0.0.1", server.port(), tls).unwrap();
+        assert_eq!(client.eval("(+ 1 2)").unwrap().value(), Some("3"));
+        assert!(client.describe().unwrap().supports_op("eval"));
+    }
+
+    #[test]
+    fn test_keys_file_and_server_name_override() {
+        let pki = generate_pki();
+        let server = start_server(&pki);
+        server.on_eval("(inc 41)", Reply::new().value("42").done());
+
+        let path = std::env::temp_dir().join(format!("nrepl-keys-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            format!("{}{}{}", pki.ca_pem, pki.client.0, pki.client.1),
+        )
+        .unwrap();
+        let tls = TlsConfig::from_keys_file(&path)
+            .unwrap()
+            .server_name("localhost");
+        std::fs::remove_file(&path).unwrap();
+
+        let mut client = NreplClient::builder()
+            .tls(tls)
+            .connect("127.0.0.1", server.port())
+            .unwrap();
+        assert_eq!(client.eval("(inc 41)").unwrap().value(), Some("42"));
+    }
+
+    #[test]
+    fn test_untrusted_server_certificate_is_rejected() {
+        let pki = generate_pki();
+        let server = start_server(&pki);
+
+        let other = generate_pki();
+        let tls = TlsConfig::new()
+            .add_ca_pem(other.ca_pem.as_bytes())
+            .unwrap()
+            .client_cert_pem(pki.client.0.as_bytes(), pki.client.1.as_bytes())
+            .unwrap();
+        let result = NreplClient::connect_tls("127.0.0.1", server.port(), tls);
+        assert!(matches!(result, Err(NreplError::IoError(_))));
+    }
+
+    #[test]
+    #[ignore = "requires the Clojure CLI (clj) on PATH"]
+    fn test_nrepl_server_with_tls_keys_file() {
+        use crate::server::NreplServer;
+
+        let pki = generate_pki();
+        let dir = std::env::temp_dir();
+        let server_keys = dir.join(format!("nrepl-server-{}.pem", uuid::Uuid::new_v4()));
+        let client_keys = dir.join(format!("nrepl-client-{}.pem", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &server_keys,
+            format!("{}{}{}", pki.ca_pem, pki.server.0, pki.server.1),
+        )
+        .unwrap();
+        std::fs::write(
+            &client_keys,
+            format!("{}{}{}", pki.ca_pem, pki.client.0, pki.client.1),
+        )
+        .unwrap();
+
+        let mut server = NreplServer::new();
+        let port = server.start_with_clj_tls(&server_keys).unwrap();
+        let tls = TlsConfig::from_keys_file(&client_keys).unwrap();
+        let mut client = NreplClient::connect_tls("127.0.0.1", port, tls).unwrap();
+        assert_eq!(client.eval("(+ 1 1)").unwrap().value(), Some("2"));
+
+        drop(client);
+        server.stop().unwrap();
+        let _ = std::fs::remove_file(server_keys);
+        let _ = std::fs::remove_file(client_keys);
+    }
+}
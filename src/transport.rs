@@ -0,0 +1,72 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A byte stream that nREPL messages can be exchanged over.
+///
+/// The client reads from one handle on its reader thread while writing through
+/// another, so a transport must be able to hand out a second handle to the same
+/// connection. Implemented for `TcpStream` and, on Unix, `UnixStream`.
+pub trait Transport: Read + Write + Send + 'static {
+    /// Returns another handle to the same underlying connection.
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>>;
+
+    /// Shuts down both directions, unblocking any thread reading from a clone.
+    fn shutdown_transport(&self) -> io::Result<()>;
+
+    /// Sets how long a write may block, or `None` to block indefinitely.
+    fn set_transport_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Transport for TcpStream {
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown_transport(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn set_transport_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn try_clone_transport(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown_transport(&self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+
+    fn set_transport_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.set_write_timeout(timeout)
+    }
+}
+
+/// The write handle shared by everything that sends on a connection.
+pub(crate) type SharedWriter = Arc<Mutex<Box<dyn Transport>>>;
+
+/// Where a client connects to, remembered so it can reconnect.
+#[derive(Debug, Clone)]
+pub(crate) enum Endpoint {
+    /// A `host:port` address.
+    Tcp(String),
+    /// The path of a Unix domain socket.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    Unix(PathBuf),
+    /// A `host:port` address spoken to over TLS.
+    #[cfg(feature = "tls")]
+    Tls {
+        address: String,
+        server_name: rustls::pki_types::ServerName<'static>,
+        config: Arc<rustls::ClientConfig>,
+    },
+}